@@ -0,0 +1,992 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use serde::Serialize;
+
+use libarchive_extractor_rs::{
+    Archive, ArchiveDiff, ArchiveEdit, ArchiveExt, ArchiveFilter, ArchiveFormat, ArchiveWriter, ChangedEntry, EntryMetadata, EntryType, ExtractAction,
+    ExtractOptions, ExtractSink, LibArchiveError, LibArchiveResult, MetadataChange, OverwritePolicy, SinkEntry, TestReport, TransformRule,
+    VerifyReport,
+};
+
+#[derive(Parser)]
+#[command(name = "archive-extractor", version, about = "List, extract, test and create archives through libarchive")]
+struct Cli {
+    /// Print results as JSON; commands reporting one record per entry print NDJSON.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the entries of an archive.
+    List {
+        archive: String,
+        /// Show type, mode, owner, size and mtime like `ls -l`.
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Extract an archive into a directory.
+    Extract {
+        archive: String,
+        /// Target directory; created when missing.
+        #[arg(short = 'C', long = "directory", default_value = ".")]
+        directory: String,
+        #[command(flatten)]
+        args: ExtractArgs,
+    },
+    /// Show the format, filters, entry counts and sizes of an archive.
+    Info {
+        archive: String,
+    },
+    /// Decompress every entry and report corruption.
+    Test {
+        archive: String,
+    },
+    /// Create an archive from files and directories.
+    Create {
+        /// Output archive; format and filter are guessed from its extension unless given.
+        #[arg(short, long)]
+        file: String,
+        #[arg(long)]
+        format: Option<ArchiveFormat>,
+        #[arg(long)]
+        filter: Option<ArchiveFilter>,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Add files and directories to an existing archive.
+    Append {
+        archive: String,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Remove entries from an archive or replace their data, in place.
+    Edit {
+        archive: String,
+        /// Remove entries matching this glob; may be repeated.
+        #[arg(long, value_parser = glob_to_regex)]
+        remove: Vec<Regex>,
+        /// Replace the data of an entry with the contents of a file, as `ENTRY=FILE`; may be
+        /// repeated.
+        #[arg(long, value_parser = parse_replacement)]
+        replace: Vec<(String, PathBuf)>,
+    },
+    /// Compare two archives entry by entry.
+    Diff {
+        old: String,
+        new: String,
+    },
+    /// Check that a directory holds what extracting an archive with the same options writes.
+    Verify {
+        archive: String,
+        /// The directory the archive was extracted into.
+        #[arg(short = 'C', long = "directory", default_value = ".")]
+        directory: String,
+        /// The pathname options given to `extract`.
+        #[command(flatten)]
+        paths: PathArgs,
+        /// Compare the owner of entries.
+        #[arg(long)]
+        owner: bool,
+        /// Do not compare permissions.
+        #[arg(long)]
+        no_perms: bool,
+    },
+    /// Write an mtree manifest of an archive with sha256 digests.
+    Manifest {
+        archive: String,
+        #[arg(short, long)]
+        file: String,
+    },
+    /// Check an archive or a directory against an mtree manifest.
+    VerifyManifest {
+        manifest: String,
+        /// An archive, or a directory the archive was extracted into.
+        target: String,
+    },
+    /// Rewrite an archive in another format or filter.
+    Convert {
+        archive: String,
+        /// Output archive; format and filter are guessed from its extension unless given.
+        #[arg(short, long)]
+        file: String,
+        #[arg(long)]
+        format: Option<ArchiveFormat>,
+        #[arg(long)]
+        filter: Option<ArchiveFilter>,
+    },
+    /// Write the data of one entry to stdout.
+    Cat {
+        archive: String,
+        entry: String,
+    },
+}
+
+/// Options choosing and renaming entries, shared by `extract` and `verify`.
+#[derive(Args)]
+struct PathArgs {
+    /// Drop this many leading components from every pathname.
+    #[arg(long, default_value_t = 0)]
+    strip_components: usize,
+    /// Only take entries matching this glob; may be repeated.
+    #[arg(long, value_parser = glob_to_regex)]
+    include: Vec<Regex>,
+    /// Skip entries matching this glob; may be repeated.
+    #[arg(long, value_parser = glob_to_regex)]
+    exclude: Vec<Regex>,
+    /// A sed expression applied to every pathname, e.g. `s/^old/new/`; may be repeated.
+    #[arg(long, value_parser = parse_transform)]
+    transform: Vec<TransformRule>,
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    #[command(flatten)]
+    paths: PathArgs,
+    #[arg(long, value_enum, default_value_t = OnExisting::Overwrite)]
+    overwrite: OnExisting,
+    /// Allow entries that escape the directory through symlinks, `..` or absolute paths.
+    #[arg(long)]
+    insecure: bool,
+    /// Restore the owner of entries.
+    #[arg(long)]
+    owner: bool,
+    /// Do not restore permissions.
+    #[arg(long)]
+    no_perms: bool,
+    /// Restore extended attributes.
+    #[arg(long)]
+    xattrs: bool,
+    /// Expand archives found inside the archive up to this depth.
+    #[arg(long, default_value_t = 0)]
+    recursive: usize,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OnExisting {
+    Overwrite,
+    Skip,
+    Newer,
+    KeepBoth,
+    Fail,
+}
+
+impl From<OnExisting> for OverwritePolicy {
+    fn from(v: OnExisting) -> Self {
+        match v {
+            OnExisting::Overwrite => OverwritePolicy::Overwrite,
+            OnExisting::Skip => OverwritePolicy::SkipExisting,
+            OnExisting::Newer => OverwritePolicy::OverwriteIfNewer,
+            OnExisting::KeepBoth => OverwritePolicy::KeepBoth,
+            OnExisting::Fail => OverwritePolicy::Fail,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+
+    let (archive, result) = match cli.command {
+        Command::List { archive, long } => {
+            let result = list(&archive, long, json);
+            (archive, result)
+        },
+        Command::Extract { archive, directory, args } => {
+            let result = extract(&archive, &directory, &args.into_options(), json);
+            (archive, result)
+        },
+        Command::Info { archive } => {
+            let result = info(&archive, json);
+            (archive, result)
+        },
+        Command::Test { archive } => {
+            let result = test(&archive, json);
+            (archive, result)
+        },
+        Command::Create { file, format, filter, paths } => {
+            let result = create(&file, format, filter, &paths);
+            (file, result)
+        },
+        Command::Append { archive, paths } => {
+            let result = append(&archive, &paths);
+            (archive, result)
+        },
+        Command::Edit { archive, remove, replace } => {
+            let result = edit(&archive, remove, &replace, json);
+            (archive, result)
+        },
+        Command::Diff { old, new } => {
+            let result = diff(&old, &new, json);
+            (old, result)
+        },
+        Command::Verify { archive, directory, paths, owner, no_perms } => {
+            let options = paths.apply(ExtractOptions::new().owner(owner).perm(!no_perms));
+            let result = verify(&archive, &directory, &options, json);
+            (archive, result)
+        },
+        Command::Manifest { archive, file } => {
+            let result = manifest(&archive, &file);
+            (archive, result)
+        },
+        Command::VerifyManifest { manifest, target } => {
+            let result = verify_manifest(&manifest, &target, json);
+            (target, result)
+        },
+        Command::Convert { archive, file, format, filter } => {
+            let result = convert(&archive, &file, format, filter, json);
+            (archive, result)
+        },
+        Command::Cat { archive, entry } => {
+            let result = cat(&archive, &entry);
+            (archive, result)
+        },
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) if json => {
+            let _ = print_json(&Record { archive: &archive, value: ErrorRecord { error: &e } });
+            ExitCode::FAILURE
+        },
+        Err(e) => {
+            eprintln!("archive-extractor: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// One JSON record, tagged with the archive it came from.
+#[derive(Serialize)]
+struct Record<'a, T: Serialize> {
+    archive: &'a str,
+    #[serde(flatten)]
+    value: T,
+}
+
+#[derive(Serialize)]
+struct ErrorRecord<'a> {
+    error: &'a LibArchiveError,
+}
+
+/// Writes `value` as one line of JSON to stdout.
+fn print_json<T: Serialize>(value: &T) -> LibArchiveResult<()> {
+    let mut out = std::io::stdout().lock();
+    serde_json::to_writer(&mut out, value).map_err(std::io::Error::from)?;
+    writeln!(out)?;
+
+    Ok(())
+}
+
+fn list(archive_path: &str, long: bool, json: bool) -> LibArchiveResult<bool> {
+    let archive = Archive::new()?;
+    let report = archive.list(archive_path)?;
+
+    if json {
+        for entry in &report.entries {
+            print_json(&Record { archive: archive_path, value: entry })?;
+        }
+        for failed in &report.failed {
+            print_json(&Record { archive: archive_path, value: failed })?;
+        }
+
+        return Ok(report.failed.is_empty());
+    }
+
+    let mut out = std::io::stdout().lock();
+    for entry in &report.entries {
+        let line = if long { long_format(entry) } else { entry.pathname.clone() };
+        if writeln!(out, "{}", line).is_err() {
+            break;
+        }
+    }
+    for failed in &report.failed {
+        eprintln!("{}: {}", failed.pathname, failed.error);
+    }
+
+    Ok(report.failed.is_empty())
+}
+
+fn info(archive_path: &str, json: bool) -> LibArchiveResult<bool> {
+    let archive = Archive::new()?;
+    let summary = archive.summary(archive_path)?;
+
+    if json {
+        print_json(&Record { archive: archive_path, value: &summary })?;
+        return Ok(true);
+    }
+
+    println!("format:       {}", summary.format.as_deref().unwrap_or("unknown"));
+    println!("filters:      {}", if summary.filters.is_empty() { "none".to_owned() } else { summary.filters.join(", ") });
+    println!("entries:      {}", summary.entries);
+    println!("files:        {}", summary.files);
+    println!("directories:  {}", summary.directories);
+    println!("symlinks:     {}", summary.symlinks);
+    println!("hardlinks:    {}", summary.hardlinks);
+    println!("others:       {}", summary.others);
+    println!("encrypted:    {}", summary.encrypted);
+    println!("total size:   {}", summary.total_size);
+    println!("archive size: {}", summary.archive_size);
+
+    Ok(true)
+}
+
+fn long_format(entry: &EntryMetadata) -> String {
+    let owner = entry.uname.clone().filter(|v| !v.is_empty()).unwrap_or_else(|| entry.uid.to_string());
+    let group = entry.gname.clone().filter(|v| !v.is_empty()).unwrap_or_else(|| entry.gid.to_string());
+    let mtime = entry.mtime.map(format_time).unwrap_or_else(|| "-".repeat(16));
+
+    let mut line = format!("{} {:>8} {:>8} {:>10} {} {}", mode_string(entry), owner, group, entry.size.unwrap_or(0), mtime, entry.pathname);
+    if let Some(target) = &entry.symlink {
+        line.push_str(&format!(" -> {}", target));
+    } else if let Some(target) = &entry.hardlink {
+        line.push_str(&format!(" link to {}", target));
+    }
+
+    line
+}
+
+fn mode_string(entry: &EntryMetadata) -> String {
+    let kind = match entry.entry_type {
+        EntryType::Directory => 'd',
+        EntryType::Symlink => 'l',
+        EntryType::Hardlink => 'h',
+        EntryType::CharacterDevice => 'c',
+        EntryType::BlockDevice => 'b',
+        EntryType::Fifo => 'p',
+        EntryType::Socket => 's',
+        EntryType::File | EntryType::Unknown => '-',
+    };
+
+    let mut result = String::from(kind);
+    let mode = entry.mode;
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        result.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        result.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        result.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+
+    result
+}
+
+/// Formats seconds since the epoch as `YYYY-MM-DD HH:MM` in UTC.
+fn format_time(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // Days to civil date, after Howard Hinnant's `civil_from_days`.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60)
+}
+
+/// Translates a shell glob into an anchored regex; `*` and `?` do not match `/`.
+///
+/// A glob without `/` is matched against every component, so `*.md` also matches `d/b.md`.
+fn glob_to_regex(glob: &str) -> Result<Regex, String> {
+    let mut pattern = String::from(if glob.contains('/') { "^" } else { "^(.*/)?" });
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            },
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    // A directory pattern also matches what is below it.
+    pattern.push_str("(/.*)?$");
+
+    Regex::new(&pattern).map_err(|e| format!("invalid glob: {}", e))
+}
+
+fn parse_transform(v: &str) -> Result<TransformRule, String> {
+    TransformRule::parse(v).map_err(|e| e.to_string())
+}
+
+impl PathArgs {
+    fn apply(self, mut options: ExtractOptions) -> ExtractOptions {
+        options = options.strip_components(self.strip_components);
+
+        let PathArgs { include, exclude, transform, .. } = self;
+        if include.is_empty() && exclude.is_empty() {
+            for rule in transform {
+                options = options.transform(rule);
+            }
+
+            return options;
+        }
+
+        // Globs are matched before the transforms, against the pathname left by --strip-components.
+        options.rewrite_pathname(move |path| {
+            let trimmed = path.trim_end_matches('/');
+            let included = include.is_empty() || include.iter().any(|v| v.is_match(trimmed));
+            if !included || exclude.iter().any(|v| v.is_match(trimmed)) {
+                return None;
+            }
+
+            Some(transform.iter().fold(path.to_owned(), |path, rule| rule.apply(&path)))
+        })
+    }
+}
+
+impl ExtractArgs {
+    fn into_options(self) -> ExtractOptions {
+        let mut options = ExtractOptions::new()
+            .overwrite_policy(self.overwrite.into())
+            .owner(self.owner)
+            .perm(!self.no_perms)
+            .xattr(self.xattrs)
+            .recursive(self.recursive);
+
+        if self.insecure {
+            options = options.secure_symlinks(false).secure_nodotdot(false).secure_noabsolute(false);
+        }
+
+        self.paths.apply(options)
+    }
+}
+
+fn extract(archive_path: &str, directory: &str, options: &ExtractOptions, json: bool) -> LibArchiveResult<bool> {
+    let archive = Archive::new()?;
+    let result = archive.extract_to_dir(archive_path, directory, options)?;
+
+    if json {
+        for info in &result {
+            print_json(&Record { archive: archive_path, value: info })?;
+        }
+
+        return Ok(result.iter().all(|v| v.error.is_none()));
+    }
+
+    let mut passed = true;
+    for info in result {
+        for warning in &info.warnings {
+            eprintln!("{}: warning: {}", info.file_name, warning);
+        }
+        if let Some(e) = &info.error {
+            eprintln!("{}: {}", info.file_name, e);
+            passed = false;
+        }
+    }
+
+    Ok(passed)
+}
+
+fn test(archive_path: &str, json: bool) -> LibArchiveResult<bool> {
+    let archive = Archive::new()?;
+    let report = archive.test(archive_path)?;
+
+    if json {
+        #[derive(Serialize)]
+        struct TestRecord<'a> {
+            passed: bool,
+            #[serde(flatten)]
+            report: &'a TestReport,
+        }
+
+        print_json(&Record { archive: archive_path, value: TestRecord { passed: report.passed(), report: &report } })?;
+        return Ok(report.passed());
+    }
+
+    for entry in &report.entries {
+        match &entry.error {
+            Some(e) => println!("FAILED {}: {}", entry.pathname, e),
+            None if !entry.passed => println!("FAILED {}", entry.pathname),
+            None => println!("OK     {}", entry.pathname),
+        }
+        for w in &entry.warnings {
+            println!("       warning: {}", w);
+        }
+    }
+    if let Some(e) = &report.error {
+        println!("FAILED {}", e);
+    }
+
+    Ok(report.passed())
+}
+
+/// Guesses the format and filter from an extension such as `.tar.gz` or `.zip`.
+fn guess_format(file: &str) -> (ArchiveFormat, ArchiveFilter) {
+    let name = file.to_ascii_lowercase();
+    let filters = [
+        (".gz", ArchiveFilter::Gzip),
+        (".tgz", ArchiveFilter::Gzip),
+        (".bz2", ArchiveFilter::Bzip2),
+        (".tbz2", ArchiveFilter::Bzip2),
+        (".xz", ArchiveFilter::Xz),
+        (".txz", ArchiveFilter::Xz),
+        (".lzma", ArchiveFilter::Lzma),
+        (".zst", ArchiveFilter::Zstd),
+        (".lz4", ArchiveFilter::Lz4),
+        (".z", ArchiveFilter::Compress),
+    ];
+    let (stem, filter) = filters.iter()
+        .find(|(ext, _)| name.ends_with(ext))
+        .map(|(ext, filter)| (&name[..name.len() - ext.len()], *filter))
+        .unwrap_or((name.as_str(), ArchiveFilter::None));
+
+    let format = if stem.ends_with(".zip") {
+        ArchiveFormat::Zip
+    } else if stem.ends_with(".7z") {
+        ArchiveFormat::SevenZip
+    } else if stem.ends_with(".cpio") {
+        ArchiveFormat::Cpio
+    } else if stem.ends_with(".iso") {
+        ArchiveFormat::Iso9660
+    } else if stem.ends_with(".xar") {
+        ArchiveFormat::Xar
+    } else if stem.ends_with(".mtree") {
+        ArchiveFormat::Mtree
+    } else {
+        ArchiveFormat::Pax
+    };
+
+    // Compressed formats are not wrapped in another filter.
+    match format {
+        ArchiveFormat::Zip | ArchiveFormat::SevenZip | ArchiveFormat::Xar => (format, ArchiveFilter::None),
+        _ => (format, filter),
+    }
+}
+
+fn create(file: &str, format: Option<ArchiveFormat>, filter: Option<ArchiveFilter>, paths: &[PathBuf]) -> LibArchiveResult<bool> {
+    let (guessed_format, guessed_filter) = guess_format(file);
+    let mut writer = ArchiveWriter::create(file, format.unwrap_or(guessed_format), filter.unwrap_or(guessed_filter))?;
+
+    for path in paths {
+        writer.add_path(path, &archive_name(path))?;
+    }
+    writer.close()?;
+
+    Ok(true)
+}
+
+fn append(archive_path: &str, paths: &[PathBuf]) -> LibArchiveResult<bool> {
+    let mut writer = ArchiveWriter::append(archive_path)?;
+    for failed in writer.failed_entries() {
+        eprintln!("{}: {}", failed.pathname, failed.error);
+    }
+    let passed = writer.failed_entries().is_empty();
+
+    for path in paths {
+        writer.add_path(path, &archive_name(path))?;
+    }
+    writer.close()?;
+
+    Ok(passed)
+}
+
+fn parse_replacement(v: &str) -> Result<(String, PathBuf), String> {
+    match v.split_once('=') {
+        Some((name, file)) if !name.is_empty() && !file.is_empty() => Ok((name.to_owned(), PathBuf::from(file))),
+        _ => Err("expected ENTRY=FILE".to_owned()),
+    }
+}
+
+fn edit(archive_path: &str, remove: Vec<Regex>, replace: &[(String, PathBuf)], json: bool) -> LibArchiveResult<bool> {
+    let mut edit = ArchiveEdit::new();
+
+    if !remove.is_empty() {
+        edit = edit.remove_if(move |v| {
+            let pathname = v.pathname.trim_start_matches("./").trim_end_matches('/');
+            remove.iter().any(|v| v.is_match(pathname))
+        });
+    }
+    for (name, file) in replace {
+        edit = edit.replace_content(name, std::fs::read(file)?);
+    }
+
+    let archive = Archive::new()?;
+    let report = archive.edit(archive_path, &edit)?;
+
+    let passed = report.missing.is_empty() && report.failed.is_empty();
+    if json {
+        print_json(&Record { archive: archive_path, value: &report })?;
+        return Ok(passed);
+    }
+
+    for pathname in &report.removed {
+        println!("removed  {}", pathname);
+    }
+    for pathname in &report.replaced {
+        println!("replaced {}", pathname);
+    }
+    for pathname in &report.missing {
+        eprintln!("{}: not found in archive", pathname);
+    }
+    for failed in &report.failed {
+        eprintln!("{}: {}", failed.pathname, failed.error);
+    }
+
+    Ok(passed)
+}
+
+/// Exits with failure when the archives differ, like `diff`.
+fn diff(old_path: &str, new_path: &str, json: bool) -> LibArchiveResult<bool> {
+    let archive = Archive::new()?;
+    let diff = archive.diff(old_path, new_path)?;
+
+    if json {
+        #[derive(Serialize)]
+        struct DiffRecord<'a> {
+            old: &'a str,
+            new: &'a str,
+            #[serde(flatten)]
+            diff: &'a ArchiveDiff,
+        }
+
+        print_json(&DiffRecord { old: old_path, new: new_path, diff: &diff })?;
+        return Ok(diff.is_empty());
+    }
+
+    for pathname in &diff.removed {
+        println!("- {}", pathname);
+    }
+    for pathname in &diff.added {
+        println!("+ {}", pathname);
+    }
+    for entry in &diff.changed {
+        println!("~ {}: {}", entry.pathname, describe_changes(entry));
+    }
+    for pathname in &diff.unreadable {
+        eprintln!("{}: header could not be read", pathname);
+    }
+
+    Ok(diff.is_empty())
+}
+
+fn describe_changes(entry: &ChangedEntry) -> String {
+    let mut changes: Vec<String> = vec!();
+    if entry.content_changed {
+        changes.push("content".to_owned());
+    }
+    for change in &entry.metadata {
+        changes.push(match change {
+            MetadataChange::EntryType { old, new } => format!("type {:?} -> {:?}", old, new),
+            MetadataChange::Mode { old, new } => format!("mode {:o} -> {:o}", old, new),
+            MetadataChange::Owner { old, new } => format!("owner {} -> {}", old, new),
+            MetadataChange::Mtime { old, new } => format!("mtime {} -> {}", old.map(format_time).unwrap_or_default(), new.map(format_time).unwrap_or_default()),
+            MetadataChange::LinkTarget { old, new } => format!("link {} -> {}", old.as_deref().unwrap_or(""), new.as_deref().unwrap_or("")),
+        });
+    }
+
+    changes.join(", ")
+}
+
+/// Exits with failure when the directory does not match, with `-` for missing entries, `+`
+/// for extra files and `~` for mismatches.
+fn verify(archive_path: &str, directory: &str, options: &ExtractOptions, json: bool) -> LibArchiveResult<bool> {
+    let archive = Archive::new()?;
+    let report = archive.verify_dir(archive_path, directory, options)?;
+
+    if json {
+        #[derive(Serialize)]
+        struct VerifyRecord<'a> {
+            archive: &'a str,
+            directory: &'a str,
+            #[serde(flatten)]
+            report: &'a VerifyReport,
+        }
+
+        print_json(&VerifyRecord { archive: archive_path, directory, report: &report })?;
+        return Ok(report.passed());
+    }
+    print_verify_report(&report);
+
+    Ok(report.passed())
+}
+
+fn print_verify_report(report: &VerifyReport) {
+    for pathname in &report.missing {
+        println!("- {}", pathname);
+    }
+    for pathname in &report.extra {
+        println!("+ {}", pathname);
+    }
+    for entry in &report.mismatched {
+        println!("~ {}: {}", entry.pathname, describe_changes(entry));
+    }
+    for pathname in &report.unreadable {
+        eprintln!("{}: header could not be read", pathname);
+    }
+}
+
+fn manifest(archive_path: &str, file: &str) -> LibArchiveResult<bool> {
+    let archive = Archive::new()?;
+    let unreadable = archive.write_manifest(archive_path, file)?;
+    for pathname in &unreadable {
+        eprintln!("{}: header could not be read", pathname);
+    }
+
+    Ok(unreadable.is_empty())
+}
+
+/// `target` is checked as a directory when it is one and as an archive otherwise; the
+/// output is the same as for `verify`.
+fn verify_manifest(manifest_path: &str, target: &str, json: bool) -> LibArchiveResult<bool> {
+    let archive = Archive::new()?;
+    let report = if Path::new(target).is_dir() {
+        archive.verify_dir_manifest(target, manifest_path)?
+    } else {
+        archive.verify_manifest(target, manifest_path)?
+    };
+
+    if json {
+        #[derive(Serialize)]
+        struct ManifestRecord<'a> {
+            manifest: &'a str,
+            target: &'a str,
+            #[serde(flatten)]
+            report: &'a VerifyReport,
+        }
+
+        print_json(&ManifestRecord { manifest: manifest_path, target, report: &report })?;
+        return Ok(report.passed());
+    }
+    print_verify_report(&report);
+
+    Ok(report.passed())
+}
+
+fn convert(archive_path: &str, file: &str, format: Option<ArchiveFormat>, filter: Option<ArchiveFilter>, json: bool) -> LibArchiveResult<bool> {
+    let (guessed_format, guessed_filter) = guess_format(file);
+    let archive = Archive::new()?;
+    let report = archive.convert(archive_path, file, format.unwrap_or(guessed_format), filter.unwrap_or(guessed_filter))?;
+
+    if json {
+        for entry in &report.entries {
+            print_json(&Record { archive: archive_path, value: entry })?;
+        }
+
+        return Ok(report.passed());
+    }
+
+    for entry in &report.entries {
+        if let Some(e) = &entry.error {
+            eprintln!("{}: {}", entry.pathname, e);
+        }
+        if !entry.lost.is_empty() {
+            let lost: Vec<&str> = entry.lost.iter().map(|v| v.name()).collect();
+            eprintln!("{}: lost {}", entry.pathname, lost.join(", "));
+        }
+    }
+
+    Ok(report.passed())
+}
+
+/// The pathname stored for `path`: relative, without leading `/` or `./`.
+fn archive_name(path: &Path) -> String {
+    let name = path.to_string_lossy();
+    let mut name = name.trim_start_matches('/');
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest.trim_start_matches('/');
+    }
+    let name = name.trim_end_matches('/');
+
+    if name.is_empty() || name == "." {
+        return ".".to_owned();
+    }
+
+    name.to_owned()
+}
+
+/// Streams the data of the entry named `name` to stdout and skips the rest. Reading stops
+/// once that entry is done.
+struct CatSink {
+    name: String,
+    /// Index of the entry once it is found.
+    index: Option<usize>,
+    entry_type: Option<EntryType>,
+    hardlink: Option<String>,
+    written: u64,
+    out: std::io::Stdout,
+    done: Arc<AtomicBool>,
+}
+
+impl ExtractSink for CatSink {
+    fn begin_entry(&mut self, entry: &SinkEntry) -> LibArchiveResult<ExtractAction> {
+        if entry.metadata().pathname.trim_start_matches("./").trim_end_matches('/') != self.name {
+            return Ok(ExtractAction::Skipped);
+        }
+
+        self.index = Some(entry.index());
+        self.entry_type = Some(entry.metadata().entry_type);
+        self.hardlink = entry.metadata().hardlink.as_deref().map(|v| v.trim_start_matches("./").to_owned());
+        self.written = 0;
+
+        Ok(ExtractAction::Created)
+    }
+
+    fn write_chunk(&mut self, offset: u64, data: &[u8]) -> LibArchiveResult<()> {
+        let mut out = self.out.lock();
+        // Holes of sparse entries come out as zeros.
+        while self.written < offset {
+            let n = (offset - self.written).min(64 * 1024) as usize;
+            out.write_all(&vec![0_u8; n])?;
+            self.written += n as u64;
+        }
+        out.write_all(data)?;
+        self.written += data.len() as u64;
+
+        Ok(())
+    }
+
+    fn finish_entry(&mut self) -> LibArchiveResult<()> {
+        self.done.store(true, Ordering::Relaxed);
+        self.out.lock().flush()?;
+
+        Ok(())
+    }
+}
+
+fn cat(archive_path: &str, name: &str) -> LibArchiveResult<bool> {
+    let archive = Archive::new()?;
+
+    let mut target = name.trim_start_matches("./").trim_end_matches('/').to_owned();
+    let mut seen: HashSet<String> = HashSet::from([target.clone()]);
+    loop {
+        let done = Arc::new(AtomicBool::new(false));
+        let mut sink = CatSink {
+            name: target.clone(),
+            index: None,
+            entry_type: None,
+            hardlink: None,
+            written: 0,
+            out: std::io::stdout(),
+            done: done.clone(),
+        };
+        let options = ExtractOptions::new().cancel_token(done);
+        // Errors on the entries before the requested one do not matter.
+        let result = match archive.extract_to_sink(archive_path, &mut sink, &options) {
+            Ok(v) | Err(LibArchiveError::Cancelled(v)) => v,
+            Err(e) => return Err(e),
+        };
+
+        let Some(index) = sink.index else {
+            eprintln!("archive-extractor: {}: not found in archive", target);
+            return Ok(false);
+        };
+        if let Some(e) = result.get(index).and_then(|v| v.error.as_ref()) {
+            eprintln!("archive-extractor: {}: {}", name, e);
+            return Ok(false);
+        }
+
+        match sink.entry_type {
+            Some(EntryType::File) => return Ok(true),
+            // The data of a tar hardlink is stored with the earlier entry it points at, so only
+            // then is the archive read again.
+            Some(EntryType::Hardlink) if sink.written == 0 => {
+                match sink.hardlink {
+                    Some(v) if seen.insert(v.clone()) => target = v,
+                    _ => {
+                        eprintln!("archive-extractor: {}: hardlink loop", name);
+                        return Ok(false);
+                    }
+                }
+            },
+            Some(EntryType::Hardlink) => return Ok(true),
+            Some(EntryType::Directory) => {
+                eprintln!("archive-extractor: {}: is a directory", name);
+                return Ok(false);
+            },
+            Some(EntryType::Symlink) => {
+                eprintln!("archive-extractor: {}: is a symlink; cat its target instead", name);
+                return Ok(false);
+            },
+            _ => {
+                eprintln!("archive-extractor: {}: not a regular file", name);
+                return Ok(false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        glob_to_regex(glob).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn glob_without_slash_matches_any_component() {
+        assert!(matches("*.md", "b.md"));
+        assert!(matches("*.md", "d/b.md"));
+        assert!(matches("d", "x/d"));
+        assert!(!matches("*.md", "b.mdx"));
+    }
+
+    #[test]
+    fn glob_with_slash_is_anchored() {
+        assert!(matches("d/*.md", "d/b.md"));
+        assert!(!matches("d/*.md", "x/d/b.md"));
+    }
+
+    #[test]
+    fn glob_star_stops_at_slash() {
+        assert!(!matches("d/*", "d"));
+        assert!(matches("a*/b", "abc/b"));
+        assert!(!matches("a*/b", "a/x/b"));
+        assert!(matches("a**/b", "a/x/b"));
+    }
+
+    #[test]
+    fn glob_question_mark() {
+        assert!(matches("?.txt", "a.txt"));
+        assert!(!matches("?.txt", "ab.txt"));
+        assert!(!matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn glob_directory_matches_its_contents() {
+        assert!(matches("d", "d"));
+        assert!(matches("d", "d/b.md"));
+        assert!(matches("src/d", "src/d/e/f"));
+        assert!(!matches("d", "dd/b.md"));
+    }
+
+    #[test]
+    fn glob_regex_characters_are_literal() {
+        assert!(matches("a+b(1).txt", "a+b(1).txt"));
+        assert!(!matches("a.txt", "abtxt"));
+        assert!(matches("[x]", "[x]"));
+        assert!(!matches("[x]", "x"));
+    }
+
+    #[test]
+    fn invalid_options_are_usage_errors() {
+        let r = Cli::try_parse_from(["archive-extractor", "extract", "a.tar", "--transform", "s/a/b"]);
+        assert!(r.is_err_and(|e| e.kind() == clap::error::ErrorKind::ValueValidation));
+
+        let r = Cli::try_parse_from(["archive-extractor", "extract", "a.tar", "--include", "*.md", "--transform", "s/a/b/"]);
+        assert!(r.is_ok());
+    }
+}
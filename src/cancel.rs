@@ -0,0 +1,13 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Checked between entries and between data blocks; once it reports cancellation the
+/// extraction stops with `LibArchiveError::Cancelled`.
+pub trait CancellationToken: Send + Sync {
+    fn is_cancelled(&self) -> bool;
+}
+
+impl CancellationToken for AtomicBool {
+    fn is_cancelled(&self) -> bool {
+        self.load(Ordering::Relaxed)
+    }
+}
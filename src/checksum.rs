@@ -0,0 +1,196 @@
+use md5::Digest;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    Md5,
+    Sha1,
+    Sha256,
+    Blake3,
+}
+
+/// Digests of an entry's data; only the requested algorithms are set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checksums {
+    pub crc32: Option<u32>,
+    pub md5: Option<[u8; 16]>,
+    pub sha1: Option<[u8; 20]>,
+    pub sha256: Option<[u8; 32]>,
+    pub blake3: Option<[u8; 32]>,
+}
+
+impl Checksums {
+    /// Lowercase hex form of one digest, as printed by `sha256sum` and friends.
+    pub fn hex(&self, algorithm: ChecksumAlgorithm) -> Option<String> {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => self.crc32.map(|v| format!("{:08x}", v)),
+            ChecksumAlgorithm::Md5 => self.md5.as_ref().map(|v| to_hex(v)),
+            ChecksumAlgorithm::Sha1 => self.sha1.as_ref().map(|v| to_hex(v)),
+            ChecksumAlgorithm::Sha256 => self.sha256.as_ref().map(|v| to_hex(v)),
+            ChecksumAlgorithm::Blake3 => self.blake3.as_ref().map(|v| to_hex(v)),
+        }
+    }
+}
+
+/// Serialized as a map from algorithm to hex digest, e.g. `{"sha256": "e3b0..."}`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Checksums {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let algorithms = [
+            ("crc32", ChecksumAlgorithm::Crc32),
+            ("md5", ChecksumAlgorithm::Md5),
+            ("sha1", ChecksumAlgorithm::Sha1),
+            ("sha256", ChecksumAlgorithm::Sha256),
+            ("blake3", ChecksumAlgorithm::Blake3),
+        ];
+        let mut map = serializer.serialize_map(None)?;
+        for (name, algorithm) in algorithms {
+            if let Some(v) = self.hex(algorithm) {
+                map.serialize_entry(name, &v)?;
+            }
+        }
+        map.end()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+/// Computes the requested digests while the data of one entry passes through.
+#[derive(Default)]
+pub(crate) struct EntryHasher {
+    crc32: Option<crc32fast::Hasher>,
+    md5: Option<md5::Md5>,
+    sha1: Option<sha1::Sha1>,
+    sha256: Option<sha2::Sha256>,
+    blake3: Option<Box<blake3::Hasher>>,
+    position: u64,
+}
+
+impl EntryHasher {
+    /// `None` when no algorithm is requested.
+    pub(crate) fn new(algorithms: &[ChecksumAlgorithm]) -> Option<Self> {
+        if algorithms.is_empty() {
+            return None;
+        }
+
+        let mut hasher = EntryHasher::default();
+        for algorithm in algorithms {
+            match algorithm {
+                ChecksumAlgorithm::Crc32 => hasher.crc32 = Some(crc32fast::Hasher::new()),
+                ChecksumAlgorithm::Md5 => hasher.md5 = Some(md5::Md5::new()),
+                ChecksumAlgorithm::Sha1 => hasher.sha1 = Some(sha1::Sha1::new()),
+                ChecksumAlgorithm::Sha256 => hasher.sha256 = Some(sha2::Sha256::new()),
+                ChecksumAlgorithm::Blake3 => hasher.blake3 = Some(Box::new(blake3::Hasher::new())),
+            }
+        }
+
+        Some(hasher)
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        if let Some(v) = &mut self.crc32 {
+            v.update(data);
+        }
+        if let Some(v) = &mut self.md5 {
+            v.update(data);
+        }
+        if let Some(v) = &mut self.sha1 {
+            v.update(data);
+        }
+        if let Some(v) = &mut self.sha256 {
+            v.update(data);
+        }
+        if let Some(v) = &mut self.blake3 {
+            v.update(data);
+        }
+        self.position += data.len() as u64;
+    }
+
+    /// Holes of sparse entries before `offset` are hashed as zeros; data behind the current
+    /// position is ignored.
+    pub(crate) fn update(&mut self, offset: u64, data: &[u8]) {
+        const ZEROS: [u8; 4096] = [0; 4096];
+        while self.position < offset {
+            let n = (offset - self.position).min(ZEROS.len() as u64) as usize;
+            self.feed(&ZEROS[..n]);
+        }
+
+        let skip = (self.position - offset).min(data.len() as u64) as usize;
+        self.feed(&data[skip..]);
+    }
+
+    pub(crate) fn finish(self) -> Checksums {
+        Checksums {
+            crc32: self.crc32.map(|v| v.finalize()),
+            md5: self.md5.map(|v| v.finalize().into()),
+            sha1: self.sha1.map(|v| v.finalize().into()),
+            sha256: self.sha256.map(|v| v.finalize().into()),
+            blake3: self.blake3.map(|v| *v.finalize().as_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ChecksumAlgorithm; 5] = [
+        ChecksumAlgorithm::Crc32,
+        ChecksumAlgorithm::Md5,
+        ChecksumAlgorithm::Sha1,
+        ChecksumAlgorithm::Sha256,
+        ChecksumAlgorithm::Blake3,
+    ];
+
+    fn dense(data: &[u8]) -> Checksums {
+        let mut hasher = EntryHasher::new(&ALL).unwrap();
+        hasher.update(0, data);
+        hasher.finish()
+    }
+
+    #[test]
+    fn sparse_holes_hash_as_zeros() {
+        let mut expected = vec![0_u8; 10_000];
+        expected[..3].copy_from_slice(b"abc");
+        expected[5000..5003].copy_from_slice(b"def");
+        expected[9997..].copy_from_slice(b"ghi");
+
+        let mut hasher = EntryHasher::new(&ALL).unwrap();
+        hasher.update(0, b"abc");
+        hasher.update(5000, b"def");
+        hasher.update(9997, b"ghi");
+
+        assert_eq!(hasher.finish(), dense(&expected));
+    }
+
+    #[test]
+    fn leading_hole_larger_than_zero_block() {
+        let mut expected = vec![0_u8; 3 * 4096 + 7];
+        expected.extend_from_slice(b"data");
+
+        let mut hasher = EntryHasher::new(&ALL).unwrap();
+        hasher.update(3 * 4096 + 7, b"data");
+
+        assert_eq!(hasher.finish(), dense(&expected));
+    }
+
+    #[test]
+    fn overlapping_data_is_ignored() {
+        let mut hasher = EntryHasher::new(&ALL).unwrap();
+        hasher.update(0, b"abcd");
+        hasher.update(2, b"cdef");
+
+        assert_eq!(hasher.finish(), dense(b"abcdef"));
+    }
+
+    #[test]
+    fn no_algorithm() {
+        assert!(EntryHasher::new(&[]).is_none());
+    }
+}
@@ -0,0 +1,253 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveEntryStruct;
+
+use crate::lossy_pathname_from_entry;
+use crate::entry::{EntryMetadata, EntryType};
+use crate::error::{LibArchiveError, LibArchiveInternalStatus, LibArchiveResult};
+use crate::handle::{archive_error, ReadArchive, WriteArchive};
+use crate::sink::read_block;
+use crate::writer::ArchiveFormat;
+
+/// Metadata of a source entry that the target format cannot hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LostMetadata {
+    Acl,
+    Xattrs,
+    /// Numeric uid and gid.
+    Owner,
+    /// User and group names.
+    OwnerNames,
+    /// The nanoseconds of the mtime.
+    SubsecondTime,
+    /// Stored as a separate, empty entry instead of a link.
+    Hardlink,
+    Symlink,
+    /// Devices, fifos and sockets.
+    SpecialFile,
+    /// The target only describes entries, e.g. mtree.
+    Data,
+}
+
+impl LostMetadata {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LostMetadata::Acl => "acl",
+            LostMetadata::Xattrs => "xattrs",
+            LostMetadata::Owner => "owner",
+            LostMetadata::OwnerNames => "owner_names",
+            LostMetadata::SubsecondTime => "subsecond_time",
+            LostMetadata::Hardlink => "hardlink",
+            LostMetadata::Symlink => "symlink",
+            LostMetadata::SpecialFile => "special_file",
+            LostMetadata::Data => "data",
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConvertedEntry {
+    pub pathname: String,
+    pub lost: Vec<LostMetadata>,
+    /// Set when the header could not be decoded or the target refused the entry; it is then
+    /// missing from the new archive.
+    pub error: Option<LibArchiveError>,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConvertReport {
+    pub entries: Vec<ConvertedEntry>,
+}
+
+impl ConvertReport {
+    /// Whether every entry was written.
+    pub fn passed(&self) -> bool {
+        self.entries.iter().all(|v| v.error.is_none())
+    }
+
+    /// Whether every entry was written with all of its metadata.
+    pub fn is_lossless(&self) -> bool {
+        self.passed() && self.entries.iter().all(|v| v.lost.is_empty())
+    }
+}
+
+/// What the libarchive writer of a format stores besides pathnames, modes and mtimes.
+struct Capabilities {
+    acl: bool,
+    xattrs: bool,
+    owner: bool,
+    owner_names: bool,
+    subsecond_time: bool,
+    hardlinks: bool,
+    symlinks: bool,
+    special_files: bool,
+    data: bool,
+}
+
+fn capabilities(format: ArchiveFormat) -> Capabilities {
+    let tar = Capabilities {
+        acl: false,
+        xattrs: false,
+        owner: true,
+        owner_names: true,
+        subsecond_time: false,
+        hardlinks: true,
+        symlinks: true,
+        special_files: true,
+        data: true,
+    };
+
+    match format {
+        ArchiveFormat::Pax => Capabilities {
+            acl: true,
+            xattrs: true,
+            subsecond_time: true,
+            ..tar
+        },
+        ArchiveFormat::Ustar | ArchiveFormat::GnuTar => tar,
+        ArchiveFormat::Zip => Capabilities {
+            owner_names: false,
+            hardlinks: false,
+            special_files: false,
+            ..tar
+        },
+        ArchiveFormat::SevenZip => Capabilities {
+            owner: false,
+            owner_names: false,
+            subsecond_time: true,
+            hardlinks: false,
+            special_files: false,
+            ..tar
+        },
+        ArchiveFormat::Cpio => Capabilities {
+            owner_names: false,
+            ..tar
+        },
+        ArchiveFormat::Iso9660 => Capabilities {
+            owner_names: false,
+            special_files: false,
+            ..tar
+        },
+        ArchiveFormat::Xar => Capabilities {
+            xattrs: true,
+            ..tar
+        },
+        ArchiveFormat::Mtree => Capabilities {
+            subsecond_time: true,
+            data: false,
+            ..tar
+        },
+    }
+}
+
+fn lost_metadata(metadata: &EntryMetadata, capabilities: &Capabilities) -> Vec<LostMetadata> {
+    let mut lost: Vec<LostMetadata> = vec!();
+
+    if !capabilities.acl && !metadata.acl.is_empty() {
+        lost.push(LostMetadata::Acl);
+    }
+    if !capabilities.xattrs && !metadata.xattrs.is_empty() {
+        lost.push(LostMetadata::Xattrs);
+    }
+    if !capabilities.owner && (metadata.uid != 0 || metadata.gid != 0) {
+        lost.push(LostMetadata::Owner);
+    }
+    let has_names = metadata.uname.as_deref().is_some_and(|v| !v.is_empty()) || metadata.gname.as_deref().is_some_and(|v| !v.is_empty());
+    if !capabilities.owner_names && has_names {
+        lost.push(LostMetadata::OwnerNames);
+    }
+    if !capabilities.subsecond_time && metadata.mtime_nsec != 0 {
+        lost.push(LostMetadata::SubsecondTime);
+    }
+    match metadata.entry_type {
+        EntryType::Hardlink if !capabilities.hardlinks => lost.push(LostMetadata::Hardlink),
+        EntryType::Symlink if !capabilities.symlinks => lost.push(LostMetadata::Symlink),
+        EntryType::CharacterDevice | EntryType::BlockDevice | EntryType::Fifo | EntryType::Socket if !capabilities.special_files => {
+            lost.push(LostMetadata::SpecialFile);
+        },
+        EntryType::File if !capabilities.data && metadata.size.unwrap_or(0) > 0 => lost.push(LostMetadata::Data),
+        _ => {},
+    }
+
+    lost
+}
+
+/// Copies the data of the current entry, filling holes of sparse entries with zeros.
+fn copy_data(read_archive: &ReadArchive, write_archive: &WriteArchive) -> LibArchiveResult<()> {
+    let zeros = [0_u8; 16 * 1024];
+    let mut position = 0_u64;
+    while let Some((offset, data)) = read_block(read_archive)? {
+        while position < offset {
+            let n = (offset - position).min(zeros.len() as u64) as usize;
+            write_archive.write_data(&zeros[..n])?;
+            position += n as u64;
+        }
+        write_archive.write_data(data)?;
+        position += data.len() as u64;
+    }
+
+    Ok(())
+}
+
+/// Writes the entry just read from `read_archive` into `write_archive`, passing the header as
+/// read so that everything libarchive knows about the entry reaches the writer.
+///
+/// Returns the error when the writer refuses the entry but can go on with the next one.
+pub(crate) fn copy_entry(read_archive: &ReadArchive, write_archive: &WriteArchive, entry: *mut ArchiveEntryStruct) -> LibArchiveResult<Option<LibArchiveError>> {
+    let write = write_archive.as_ptr();
+    // The pax reader leaves the file type of hardlinks unset, which zip and 7zip refuse.
+    unsafe {
+        if libarchive3_sys::archive_entry_filetype(entry) == 0 && !libarchive3_sys::archive_entry_hardlink(entry).is_null() {
+            libarchive3_sys::archive_entry_set_filetype(entry, libarchive3_sys::AE_IFREG as _);
+        }
+    }
+    let status_code = unsafe { libarchive3_sys::archive_write_header(write, entry) };
+    if status_code < libarchive3_sys::ARCHIVE_WARN {
+        let error = archive_error(write, status_code);
+        if matches!(LibArchiveInternalStatus::from(status_code), LibArchiveInternalStatus::ArchiveFatal) {
+            return Err(error);
+        }
+
+        return Ok(Some(error));
+    }
+
+    copy_data(read_archive, write_archive)?;
+    write_archive.finish_entry()?;
+
+    Ok(None)
+}
+
+/// Streams every entry of `read_archive` into `write_archive`.
+pub(crate) fn convert_entries(read_archive: &ReadArchive, write_archive: &WriteArchive, format: ArchiveFormat) -> LibArchiveResult<ConvertReport> {
+    let capabilities = capabilities(format);
+    let mut report = ConvertReport::default();
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    while read_archive.next_header(&mut entry)? {
+        let metadata = match EntryMetadata::from_entry(entry) {
+            Ok(v) => v,
+            Err(e) => {
+                report.entries.push(ConvertedEntry {
+                    pathname: lossy_pathname_from_entry(entry),
+                    lost: vec!(),
+                    error: Some(e),
+                });
+
+                continue;
+            }
+        };
+        let lost = lost_metadata(&metadata, &capabilities);
+        let error = copy_entry(read_archive, write_archive, entry)?;
+
+        report.entries.push(ConvertedEntry {
+            pathname: metadata.pathname,
+            lost,
+            error,
+        });
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,301 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveEntryStruct;
+
+use std::collections::BTreeMap;
+
+use crate::checksum::{ChecksumAlgorithm, EntryHasher};
+use crate::entry::{EntryMetadata, EntryType};
+use crate::error::LibArchiveResult;
+use crate::handle::ReadArchive;
+use crate::sink::read_block;
+use crate::lossy_pathname_from_entry;
+use crate::vfs::normalize;
+
+/// A difference in the metadata of an entry present in both archives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MetadataChange {
+    EntryType { old: EntryType, new: EntryType },
+    /// Permission bits.
+    Mode { old: u32, new: u32 },
+    /// Owner by name when both sides have names, by uid and gid otherwise.
+    Owner { old: String, new: String },
+    /// Whole seconds; formats differ too much in sub-second precision to compare it.
+    Mtime { old: Option<i64>, new: Option<i64> },
+    /// Symlink or hardlink target.
+    LinkTarget { old: Option<String>, new: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChangedEntry {
+    pub pathname: String,
+    /// The data differs by sha256; hardlinks are compared by the data of their target.
+    pub content_changed: bool,
+    pub metadata: Vec<MetadataChange>,
+}
+
+/// Entries of two archives matched by normalized pathname.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchiveDiff {
+    /// Only in the new archive.
+    pub added: Vec<String>,
+    /// Only in the old archive.
+    pub removed: Vec<String>,
+    pub changed: Vec<ChangedEntry>,
+    /// Entries of either archive whose header could not be decoded, by lossily decoded
+    /// pathname; they are left out of the comparison.
+    pub unreadable: Vec<String>,
+}
+
+impl ArchiveDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && self.unreadable.is_empty()
+    }
+}
+
+/// The header of an entry together with the sha256 of its data.
+pub(crate) struct IndexedEntry {
+    pub(crate) metadata: EntryMetadata,
+    pub(crate) sha256: Option<[u8; 32]>,
+}
+
+/// The entries of an archive and the pathnames of those whose header could not be decoded.
+pub(crate) struct ArchiveIndex {
+    pub(crate) entries: BTreeMap<String, IndexedEntry>,
+    pub(crate) unreadable: Vec<String>,
+}
+
+/// Reads every entry of `read_archive`, hashing the data of regular files. Keyed by
+/// normalized pathname; a later entry of the same name replaces an earlier one, as on
+/// extraction. Hardlinks get the digest of their target.
+pub(crate) fn index_entries(read_archive: &ReadArchive) -> LibArchiveResult<ArchiveIndex> {
+    let mut index: BTreeMap<String, IndexedEntry> = BTreeMap::new();
+    let mut unreadable: Vec<String> = vec!();
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    while read_archive.next_header(&mut entry)? {
+        let Ok(metadata) = EntryMetadata::from_entry(entry) else {
+            unreadable.push(lossy_pathname_from_entry(entry));
+            continue;
+        };
+
+        let sha256 = if metadata.entry_type == EntryType::File {
+            let mut hasher = EntryHasher::new(&[ChecksumAlgorithm::Sha256]).unwrap_or_default();
+            while let Some((offset, data)) = read_block(read_archive)? {
+                hasher.update(offset, data);
+            }
+            // A trailing hole of a sparse entry.
+            hasher.update(metadata.size.unwrap_or(0), &[]);
+            hasher.finish().sha256
+        } else {
+            None
+        };
+
+        if let Some(pathname) = normalize(&metadata.pathname).filter(|v| !v.is_empty()) {
+            index.insert(pathname, IndexedEntry { metadata, sha256 });
+        }
+    }
+
+    let hardlinks: Vec<(String, String)> = index.iter()
+        .filter_map(|(k, v)| Some((k.clone(), normalize(v.metadata.hardlink.as_deref()?)?)))
+        .collect();
+    for (pathname, target) in hardlinks {
+        let sha256 = index.get(&target).and_then(|v| v.sha256);
+        if let Some(v) = index.get_mut(&pathname) {
+            v.sha256 = sha256;
+        }
+    }
+
+    Ok(ArchiveIndex { entries: index, unreadable })
+}
+
+fn owner_of(metadata: &EntryMetadata, by_name: bool) -> String {
+    if by_name {
+        format!("{}:{}", metadata.uname.as_deref().unwrap_or(""), metadata.gname.as_deref().unwrap_or(""))
+    } else {
+        format!("{}:{}", metadata.uid, metadata.gid)
+    }
+}
+
+fn has_names(metadata: &EntryMetadata) -> bool {
+    metadata.uname.as_deref().is_some_and(|v| !v.is_empty()) && metadata.gname.as_deref().is_some_and(|v| !v.is_empty())
+}
+
+fn link_target(metadata: &EntryMetadata) -> Option<String> {
+    match metadata.entry_type {
+        EntryType::Hardlink => metadata.hardlink.as_deref().and_then(normalize),
+        EntryType::Symlink => metadata.symlink.clone(),
+        _ => None,
+    }
+}
+
+pub(crate) fn metadata_changes(old: &EntryMetadata, new: &EntryMetadata) -> Vec<MetadataChange> {
+    let mut changes: Vec<MetadataChange> = vec!();
+
+    if old.entry_type != new.entry_type {
+        changes.push(MetadataChange::EntryType { old: old.entry_type, new: new.entry_type });
+    }
+    if old.mode & 0o7777 != new.mode & 0o7777 {
+        changes.push(MetadataChange::Mode { old: old.mode & 0o7777, new: new.mode & 0o7777 });
+    }
+    let by_name = has_names(old) && has_names(new);
+    let (old_owner, new_owner) = (owner_of(old, by_name), owner_of(new, by_name));
+    if old_owner != new_owner {
+        changes.push(MetadataChange::Owner { old: old_owner, new: new_owner });
+    }
+    if old.mtime != new.mtime {
+        changes.push(MetadataChange::Mtime { old: old.mtime, new: new.mtime });
+    }
+    let (old_target, new_target) = (link_target(old), link_target(new));
+    if old_target != new_target {
+        changes.push(MetadataChange::LinkTarget { old: old_target, new: new_target });
+    }
+
+    changes
+}
+
+pub(crate) fn diff_indexes(old: &BTreeMap<String, IndexedEntry>, new: &BTreeMap<String, IndexedEntry>) -> ArchiveDiff {
+    let mut diff = ArchiveDiff::default();
+
+    for (pathname, old_entry) in old {
+        let Some(new_entry) = new.get(pathname) else {
+            diff.removed.push(pathname.clone());
+            continue;
+        };
+
+        let content_changed = match (old_entry.sha256, new_entry.sha256) {
+            (Some(a), Some(b)) => a != b,
+            // Not both files; the type change is reported instead.
+            _ => false,
+        };
+        let metadata = metadata_changes(&old_entry.metadata, &new_entry.metadata);
+        if content_changed || !metadata.is_empty() {
+            diff.changed.push(ChangedEntry {
+                pathname: pathname.clone(),
+                content_changed,
+                metadata,
+            });
+        }
+    }
+    diff.added = new.keys().filter(|v| !old.contains_key(*v)).cloned().collect();
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(pathname: &str, data: u8) -> (String, IndexedEntry) {
+        let mut metadata = EntryMetadata::new(pathname, EntryType::File);
+        metadata.mtime = Some(1_700_000_000);
+        (pathname.to_owned(), IndexedEntry { metadata, sha256: Some([data; 32]) })
+    }
+
+    fn index(entries: Vec<(String, IndexedEntry)>) -> BTreeMap<String, IndexedEntry> {
+        entries.into_iter().collect()
+    }
+
+    #[test]
+    fn identical() {
+        let diff = diff_indexes(&index(vec![file("a", 1), file("b", 2)]), &index(vec![file("a", 1), file("b", 2)]));
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn added_and_removed() {
+        let diff = diff_indexes(&index(vec![file("a", 1), file("b", 2)]), &index(vec![file("b", 2), file("c", 3)]));
+        assert_eq!(diff.removed, vec!["a"]);
+        assert_eq!(diff.added, vec!["c"]);
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn content_changed() {
+        let diff = diff_indexes(&index(vec![file("a", 1)]), &index(vec![file("a", 2)]));
+        assert_eq!(diff.changed, vec![ChangedEntry {
+            pathname: "a".to_owned(),
+            content_changed: true,
+            metadata: vec!(),
+        }]);
+    }
+
+    #[test]
+    fn metadata_only() {
+        let (name, mut new) = file("a", 1);
+        new.metadata.mode = 0o755;
+        new.metadata.mtime = Some(1_700_000_001);
+
+        let diff = diff_indexes(&index(vec![file("a", 1)]), &index(vec![(name, new)]));
+        assert_eq!(diff.changed, vec![ChangedEntry {
+            pathname: "a".to_owned(),
+            content_changed: false,
+            metadata: vec![
+                MetadataChange::Mode { old: 0o644, new: 0o755 },
+                MetadataChange::Mtime { old: Some(1_700_000_000), new: Some(1_700_000_001) },
+            ],
+        }]);
+    }
+
+    #[test]
+    fn type_change_is_not_a_content_change() {
+        let (name, mut new) = file("a", 1);
+        new.metadata.entry_type = EntryType::Symlink;
+        new.metadata.symlink = Some("b".to_owned());
+        new.sha256 = None;
+
+        let diff = diff_indexes(&index(vec![file("a", 1)]), &index(vec![(name, new)]));
+        assert_eq!(diff.changed[0].metadata, vec![
+            MetadataChange::EntryType { old: EntryType::File, new: EntryType::Symlink },
+            MetadataChange::LinkTarget { old: None, new: Some("b".to_owned()) },
+        ]);
+        assert!(!diff.changed[0].content_changed);
+    }
+
+    #[test]
+    fn owner_by_name_when_both_have_names() {
+        let mut old = EntryMetadata::new("a", EntryType::File);
+        old.uid = 1000;
+        old.uname = Some("alice".to_owned());
+        old.gname = Some("staff".to_owned());
+        let mut new = old.clone();
+        new.uid = 1001;
+
+        // Same names, different ids: unchanged.
+        assert!(metadata_changes(&old, &new).is_empty());
+
+        new.uname = None;
+        assert_eq!(metadata_changes(&old, &new), vec![MetadataChange::Owner { old: "1000:0".to_owned(), new: "1001:0".to_owned() }]);
+    }
+
+    #[test]
+    fn file_type_bits_and_subseconds_are_ignored() {
+        let old = EntryMetadata::new("a", EntryType::File);
+        let mut new = old.clone();
+        new.mode |= 0o100000;
+        new.mtime_nsec = 5;
+
+        assert!(metadata_changes(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn setuid_change_is_detected() {
+        let old = EntryMetadata::new("a", EntryType::File);
+        let mut new = old.clone();
+        new.mode |= 0o4000;
+
+        assert_eq!(metadata_changes(&old, &new), vec![MetadataChange::Mode { old: 0o644, new: 0o4644 }]);
+    }
+
+    #[test]
+    fn unreadable_entries_make_the_diff_non_empty() {
+        let diff = ArchiveDiff {
+            unreadable: vec!["caf\u{fffd}".to_owned()],
+            ..ArchiveDiff::default()
+        };
+        assert!(!diff.is_empty());
+    }
+}
@@ -0,0 +1,341 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveEntryStruct;
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::convert::copy_entry;
+use crate::lossy_pathname_from_entry;
+use crate::entry::{EntryMetadata, EntryType, FailedEntry};
+use crate::error::{LibArchiveError, LibArchiveResult};
+use crate::handle::{Entry, ReadArchive, WriteArchive};
+use crate::vfs::normalize;
+use crate::writer::open_rewrite;
+
+/// User callback for `ArchiveEdit::remove_if`.
+type EntryPredicateFn = dyn Fn(&EntryMetadata) -> bool + Send + Sync;
+
+#[derive(Clone)]
+pub struct EntryPredicate(Arc<EntryPredicateFn>);
+
+impl std::fmt::Debug for EntryPredicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("EntryPredicate")
+    }
+}
+
+/// Changes applied by `ArchiveExt::edit`, which rewrites an archive without extracting it.
+///
+/// Pathnames are compared after normalization, so `./a/b` and `a/b/` name the same entry.
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveEdit {
+    predicates: Vec<EntryPredicate>,
+    replacements: HashMap<String, Arc<[u8]>>,
+}
+
+impl ArchiveEdit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes every entry for which `f` returns `true`.
+    pub fn remove_if<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&EntryMetadata) -> bool + Send + Sync + 'static,
+    {
+        self.predicates.push(EntryPredicate(Arc::new(f)));
+        self
+    }
+
+    /// Removes the entry named `pathname`.
+    pub fn remove(self, pathname: &str) -> Self {
+        let pathname = normalize(pathname).unwrap_or_default();
+        self.remove_if(move |v| normalize(&v.pathname).as_deref() == Some(pathname.as_str()))
+    }
+
+    /// Replaces the data of the entry named `pathname`, keeping its other metadata. A hardlink
+    /// becomes a regular file with the new data.
+    pub fn replace_content(mut self, pathname: &str, data: impl Into<Vec<u8>>) -> Self {
+        let pathname = normalize(pathname).unwrap_or_default();
+        self.replacements.insert(pathname, Arc::from(data.into()));
+        self
+    }
+
+    fn is_removed(&self, metadata: &EntryMetadata) -> bool {
+        self.predicates.iter().any(|v| (v.0)(metadata))
+    }
+
+    /// Whether any entry can be removed, which is when hardlinks need `plan_links`.
+    pub(crate) fn removes_entries(&self) -> bool {
+        !self.predicates.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct EditReport {
+    /// Entries left out. Hardlinks to a removed file are kept: the first becomes a regular file
+    /// with its data and the later ones link to that.
+    pub removed: Vec<String>,
+    pub replaced: Vec<String>,
+    /// Replacements whose entry was not found.
+    pub missing: Vec<String>,
+    /// Entries copied unchanged.
+    pub kept: usize,
+    /// Entries that could not be edited. One whose header could not be decoded is copied
+    /// unchanged, since nothing can be matched against it, and so is a directory named by a
+    /// replacement; one the writer refuses is left out.
+    pub failed: Vec<FailedEntry>,
+}
+
+/// How hardlinks outlive the entries they point at, worked out by `plan_links` before the
+/// rewrite. Keyed by normalized pathname.
+#[derive(Debug, Default)]
+pub(crate) struct LinkPlan {
+    /// Removed file to the pathname of its first kept hardlink, which is written with the data
+    /// of the file in its place.
+    promoted: HashMap<String, String>,
+    /// Removed entry to the pathname its kept hardlinks point at instead.
+    redirected: HashMap<String, String>,
+}
+
+/// Reads the headers of `read_archive` to find the hardlinks that `edit` keeps while removing
+/// their target.
+pub(crate) fn plan_links(read_archive: &ReadArchive, edit: &ArchiveEdit) -> LibArchiveResult<LinkPlan> {
+    let mut plan = LinkPlan::default();
+    // Removed entries, with the target of those that are hardlinks themselves.
+    let mut removed: HashMap<String, Option<String>> = HashMap::new();
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    while read_archive.next_header(&mut entry)? {
+        let Ok(metadata) = EntryMetadata::from_entry(entry) else {
+            continue;
+        };
+        let pathname = normalize(&metadata.pathname).unwrap_or_default();
+        let target = metadata.hardlink.as_deref().and_then(normalize);
+        if edit.is_removed(&metadata) {
+            removed.insert(pathname, target);
+            continue;
+        }
+
+        let Some(target) = target else {
+            continue;
+        };
+        if edit.replacements.contains_key(&pathname) || plan.redirected.contains_key(&target) {
+            continue;
+        }
+
+        // The entry holding the data, past removed hardlinks.
+        let mut holder = target.clone();
+        let mut seen: HashSet<String> = HashSet::new();
+        while let Some(Some(next)) = removed.get(&holder) {
+            if !seen.insert(next.clone()) {
+                break;
+            }
+            holder = next.clone();
+        }
+
+        let new_target = if let Some(v) = plan.redirected.get(&holder) {
+            v.clone()
+        } else if removed.contains_key(&holder) {
+            plan.promoted.insert(holder.clone(), metadata.pathname.clone());
+            plan.redirected.insert(holder.clone(), metadata.pathname.clone());
+            metadata.pathname.clone()
+        } else {
+            holder.clone()
+        };
+        if target != holder || plan.promoted.get(&holder) != Some(&metadata.pathname) {
+            plan.redirected.insert(target, new_target);
+        }
+    }
+
+    Ok(plan)
+}
+
+fn set_entry_path(entry: *mut ArchiveEntryStruct, pathname: Option<&str>, hardlink: Option<&str>) -> LibArchiveResult<()> {
+    if let Some(pathname) = pathname {
+        let Ok(pathname) = CString::new(pathname) else {
+            return Err(LibArchiveError::NulError);
+        };
+        unsafe { libarchive3_sys::archive_entry_set_pathname_utf8(entry, pathname.as_ptr()) };
+    }
+    if let Some(hardlink) = hardlink {
+        let Ok(hardlink) = CString::new(hardlink) else {
+            return Err(LibArchiveError::NulError);
+        };
+        unsafe { libarchive3_sys::archive_entry_set_hardlink_utf8(entry, hardlink.as_ptr()) };
+    }
+
+    Ok(())
+}
+
+/// Writes `metadata` with `data` as a regular file.
+pub(crate) fn write_replacement(write_archive: &WriteArchive, metadata: &EntryMetadata, data: &[u8]) -> LibArchiveResult<()> {
+    let mut metadata = metadata.clone();
+    metadata.entry_type = EntryType::File;
+    metadata.hardlink = None;
+    metadata.symlink = None;
+    metadata.size = Some(data.len() as u64);
+
+    let entry = Entry::new()?;
+    metadata.fill_entry(entry.as_ptr())?;
+    write_archive.write_header(&entry)?;
+    if !data.is_empty() {
+        write_archive.write_data(data)?;
+    }
+    write_archive.finish_entry()
+}
+
+/// Rewrites the archive at `path` into a temporary file that replaces it once complete.
+pub(crate) fn edit_entries(read_archive: &ReadArchive, path: &Path, edit: &ArchiveEdit, links: &LinkPlan) -> LibArchiveResult<EditReport> {
+    let mut report = EditReport::default();
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    let has_entries = read_archive.next_header(&mut entry)?;
+    let (write_archive, replace) = open_rewrite(read_archive, path)?;
+
+    let mut promoted_paths: HashSet<String> = HashSet::new();
+    let mut replaced_paths: HashSet<String> = HashSet::new();
+    if has_entries {
+        loop {
+            let metadata = match EntryMetadata::from_entry(entry) {
+                Ok(v) => v,
+                Err(e) => {
+                    let error = copy_entry(read_archive, &write_archive, entry)?.unwrap_or(e);
+                    report.failed.push(FailedEntry {
+                        pathname: lossy_pathname_from_entry(entry),
+                        error,
+                    });
+
+                    if !read_archive.next_header(&mut entry)? {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let pathname = normalize(&metadata.pathname).unwrap_or_default();
+            let redirected = metadata.hardlink.as_deref()
+                .and_then(normalize)
+                .and_then(|v| links.redirected.get(&v));
+
+            let copied = if edit.is_removed(&metadata) {
+                report.removed.push(metadata.pathname);
+                match links.promoted.get(&pathname) {
+                    Some(link) => {
+                        promoted_paths.insert(normalize(link).unwrap_or_default());
+                        set_entry_path(entry, Some(link), None)?;
+                        copy_entry(read_archive, &write_archive, entry)?.map(|error| (link.clone(), error))
+                    },
+                    None => None,
+                }
+            } else if promoted_paths.contains(&pathname) {
+                // Already written with the data of the removed file it linked to.
+                report.kept += 1;
+                None
+            } else if let Some(data) = edit.replacements.get(&pathname) {
+                if metadata.entry_type == EntryType::Directory {
+                    replaced_paths.insert(pathname);
+                    copy_entry(read_archive, &write_archive, entry)?;
+                    Some((metadata.pathname, LibArchiveError::IsNotFile))
+                } else {
+                    write_replacement(&write_archive, &metadata, data)?;
+                    replaced_paths.insert(pathname);
+                    report.replaced.push(metadata.pathname);
+                    None
+                }
+            } else {
+                if let Some(target) = redirected {
+                    set_entry_path(entry, None, Some(target))?;
+                }
+                match copy_entry(read_archive, &write_archive, entry)? {
+                    Some(error) => Some((metadata.pathname, error)),
+                    None => {
+                        report.kept += 1;
+                        None
+                    }
+                }
+            };
+            if let Some((pathname, error)) = copied {
+                report.failed.push(FailedEntry { pathname, error });
+            }
+
+            if !read_archive.next_header(&mut entry)? {
+                break;
+            }
+        }
+    }
+
+    write_archive.close()?;
+    replace.commit()?;
+
+    let mut missing: Vec<String> = edit.replacements.keys()
+        .filter(|v| !replaced_paths.contains(*v))
+        .cloned()
+        .collect();
+    missing.sort();
+    report.missing = missing;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArchiveExt;
+    use crate::tests::TempDir;
+    use crate::writer::{ArchiveFilter, ArchiveFormat, ArchiveWriter};
+
+    fn linked_archive(path: &str) {
+        let mut writer = ArchiveWriter::create(path, ArchiveFormat::Pax, ArchiveFilter::None).unwrap();
+        writer.write_entry(&EntryMetadata::new("d", EntryType::Directory), b"").unwrap();
+        writer.write_entry(&EntryMetadata::new("a", EntryType::File), b"data").unwrap();
+        for name in ["b", "c"] {
+            let mut metadata = EntryMetadata::new(name, EntryType::Hardlink);
+            metadata.hardlink = Some("a".to_owned());
+            writer.write_entry(&metadata, b"").unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn removing_a_target_keeps_its_hardlinks() {
+        let dir = TempDir::new("edit-remove-target");
+        let path = dir.0.join("a.tar");
+        let path_str = path.to_str().unwrap();
+        linked_archive(path_str);
+        let sha256 = crate::Archive.index(path_str).unwrap().entries["a"].sha256;
+
+        let report = crate::Archive.edit(path_str, &ArchiveEdit::new().remove("a")).unwrap();
+        assert_eq!(report.removed, vec!["a"]);
+        assert_eq!(report.kept, 3);
+        assert!(report.failed.is_empty());
+
+        let index = crate::Archive.index(path_str).unwrap().entries;
+        assert_eq!(index.keys().collect::<Vec<_>>(), ["b", "c", "d"]);
+        assert_eq!(index["b"].metadata.entry_type, EntryType::File);
+        assert_eq!(index["b"].metadata.hardlink, None);
+        assert_eq!(index["c"].metadata.hardlink.as_deref(), Some("b"));
+        assert_eq!(index["b"].sha256, sha256);
+        assert_eq!(index["c"].sha256, sha256);
+    }
+
+    #[test]
+    fn replacing_a_directory_is_refused() {
+        let dir = TempDir::new("edit-replace-dir");
+        let path = dir.0.join("a.tar");
+        let path_str = path.to_str().unwrap();
+        linked_archive(path_str);
+
+        let report = crate::Archive.edit(path_str, &ArchiveEdit::new().replace_content("d", "x")).unwrap();
+        assert!(report.replaced.is_empty());
+        assert!(report.missing.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].pathname, "d/");
+        assert!(matches!(report.failed[0].error, LibArchiveError::IsNotFile));
+
+        let index = crate::Archive.index(path_str).unwrap().entries;
+        assert_eq!(index["d"].metadata.entry_type, EntryType::Directory);
+    }
+}
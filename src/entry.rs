@@ -0,0 +1,428 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveEntryStruct;
+
+use libc::{c_char, c_int, c_void};
+use std::ffi::CString;
+
+use crate::convert_c_char_to_string;
+use crate::error::{LibArchiveError, LibArchiveInternalStatus, LibArchiveResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EntryType {
+    File,
+    Directory,
+    Symlink,
+    Hardlink,
+    CharacterDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Xattr {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AclType {
+    Access,
+    Default,
+    Allow,
+    Deny,
+    Audit,
+    Alarm,
+}
+
+impl AclType {
+    fn from_raw(v: c_int) -> Option<AclType> {
+        match v {
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_ACCESS => Some(AclType::Access),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_DEFAULT => Some(AclType::Default),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_ALLOW => Some(AclType::Allow),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_DENY => Some(AclType::Deny),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_AUDIT => Some(AclType::Audit),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_ALARM => Some(AclType::Alarm),
+            _ => None,
+        }
+    }
+
+    fn to_raw(self) -> c_int {
+        match self {
+            AclType::Access => libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_ACCESS,
+            AclType::Default => libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_DEFAULT,
+            AclType::Allow => libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_ALLOW,
+            AclType::Deny => libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_DENY,
+            AclType::Audit => libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_AUDIT,
+            AclType::Alarm => libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_ALARM,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AclTag {
+    User,
+    UserObj,
+    Group,
+    GroupObj,
+    Mask,
+    Other,
+    Everyone,
+}
+
+impl AclTag {
+    fn from_raw(v: c_int) -> Option<AclTag> {
+        match v {
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_USER => Some(AclTag::User),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_USER_OBJ => Some(AclTag::UserObj),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_GROUP => Some(AclTag::Group),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_GROUP_OBJ => Some(AclTag::GroupObj),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_MASK => Some(AclTag::Mask),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_OTHER => Some(AclTag::Other),
+            libarchive3_sys::ARCHIVE_ENTRY_ACL_EVERYONE => Some(AclTag::Everyone),
+            _ => None,
+        }
+    }
+
+    fn to_raw(self) -> c_int {
+        match self {
+            AclTag::User => libarchive3_sys::ARCHIVE_ENTRY_ACL_USER,
+            AclTag::UserObj => libarchive3_sys::ARCHIVE_ENTRY_ACL_USER_OBJ,
+            AclTag::Group => libarchive3_sys::ARCHIVE_ENTRY_ACL_GROUP,
+            AclTag::GroupObj => libarchive3_sys::ARCHIVE_ENTRY_ACL_GROUP_OBJ,
+            AclTag::Mask => libarchive3_sys::ARCHIVE_ENTRY_ACL_MASK,
+            AclTag::Other => libarchive3_sys::ARCHIVE_ENTRY_ACL_OTHER,
+            AclTag::Everyone => libarchive3_sys::ARCHIVE_ENTRY_ACL_EVERYONE,
+        }
+    }
+}
+
+/// One POSIX.1e or NFSv4 ACL entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AclEntry {
+    pub acl_type: AclType,
+    /// `ARCHIVE_ENTRY_ACL_*` permission bits, e.g. `4` read, `2` write, `1` execute.
+    pub permset: u32,
+    pub tag: AclTag,
+    /// uid or gid for `User` and `Group` entries.
+    pub qualifier: Option<i64>,
+    pub name: Option<String>,
+}
+
+/// An entry that could not be handled, reported in place of failing the whole operation.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FailedEntry {
+    /// Decoded lossily when the header holds invalid UTF-8.
+    pub pathname: String,
+    pub error: LibArchiveError,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntryMetadata {
+    pub pathname: String,
+    pub entry_type: EntryType,
+    pub size: Option<u64>,
+    pub mode: u32,
+    pub uid: i64,
+    pub gid: i64,
+    pub uname: Option<String>,
+    pub gname: Option<String>,
+    pub mtime: Option<i64>,
+    pub mtime_nsec: i64,
+    pub symlink: Option<String>,
+    pub hardlink: Option<String>,
+    pub is_encrypted: bool,
+    pub xattrs: Vec<Xattr>,
+    pub acl: Vec<AclEntry>,
+    /// The ACL in the text form of `getfacl`/`nfs4_getfacl`, with numeric ids appended.
+    pub acl_text: Option<String>,
+}
+
+const ACL_TYPE_ALL: c_int = libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_POSIX1E | libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_NFS4;
+
+fn read_xattrs(entry: *mut ArchiveEntryStruct) -> Vec<Xattr> {
+    let mut result: Vec<Xattr> = vec!();
+    unsafe { libarchive3_sys::archive_entry_xattr_reset(entry) };
+    loop {
+        let mut name: *const c_char = std::ptr::null();
+        let mut value: *const c_void = std::ptr::null();
+        let mut size = 0_usize;
+        if unsafe { libarchive3_sys::archive_entry_xattr_next(entry, &mut name, &mut value, &mut size) } != libarchive3_sys::ARCHIVE_OK {
+            break;
+        }
+
+        let Some(name) = convert_c_char_to_string(name) else {
+            continue;
+        };
+        let value = if value.is_null() {
+            vec!()
+        } else {
+            unsafe { std::slice::from_raw_parts(value as *const u8, size) }.to_vec()
+        };
+        result.push(Xattr { name, value });
+    }
+
+    result
+}
+
+fn read_acl(entry: *mut ArchiveEntryStruct) -> Vec<AclEntry> {
+    let mut result: Vec<AclEntry> = vec!();
+    if unsafe { libarchive3_sys::archive_entry_acl_reset(entry, ACL_TYPE_ALL) } <= 0 {
+        return result;
+    }
+
+    loop {
+        let mut acl_type: c_int = 0;
+        let mut permset: c_int = 0;
+        let mut tag: c_int = 0;
+        let mut qualifier: c_int = -1;
+        let mut name: *const c_char = std::ptr::null();
+        if unsafe { libarchive3_sys::archive_entry_acl_next(entry, ACL_TYPE_ALL, &mut acl_type, &mut permset, &mut tag, &mut qualifier, &mut name) } != libarchive3_sys::ARCHIVE_OK {
+            break;
+        }
+
+        let (Some(acl_type), Some(tag)) = (AclType::from_raw(acl_type), AclTag::from_raw(tag)) else {
+            continue;
+        };
+        result.push(AclEntry {
+            acl_type,
+            permset: permset as u32,
+            tag,
+            qualifier: if qualifier < 0 { None } else { Some(qualifier as i64) },
+            name: convert_c_char_to_string(name),
+        });
+    }
+
+    result
+}
+
+fn read_acl_text(entry: *mut ArchiveEntryStruct) -> Option<String> {
+    let text = unsafe { libarchive3_sys::archive_entry_acl_to_text(entry, std::ptr::null_mut(), libarchive3_sys::ARCHIVE_ENTRY_ACL_STYLE_EXTRA_ID) };
+    if text.is_null() {
+        return None;
+    }
+
+    let result = convert_c_char_to_string(text);
+    unsafe { libc::free(text as *mut c_void) };
+
+    result
+}
+
+/// `ARCHIVE_ENTRY_DIGEST_SHA256` of `archive_entry.h`, which libarchive3_sys does not export.
+const ARCHIVE_ENTRY_DIGEST_SHA256: c_int = 0x00000004;
+
+/// The sha256 digest an mtree manifest gives for the entry.
+pub(crate) fn entry_sha256(entry: *mut ArchiveEntryStruct) -> Option<[u8; 32]> {
+    let digest = unsafe { libarchive3_sys::archive_entry_digest(entry, ARCHIVE_ENTRY_DIGEST_SHA256) };
+    if digest.is_null() {
+        return None;
+    }
+
+    let mut sha256 = [0_u8; 32];
+    sha256.copy_from_slice(unsafe { std::slice::from_raw_parts(digest, 32) });
+    // Unset digests read as zeros.
+    Some(sha256).filter(|v| v.iter().any(|b| *b != 0))
+}
+
+/// Sets the sha256 digest the mtree writer reports for an entry written without data.
+pub(crate) fn set_entry_sha256(entry: *mut ArchiveEntryStruct, sha256: &[u8; 32]) -> LibArchiveResult<()> {
+    let r = unsafe { libarchive3_sys::archive_entry_set_digest(entry, ARCHIVE_ENTRY_DIGEST_SHA256, sha256.as_ptr()) };
+    if r != libarchive3_sys::ARCHIVE_OK {
+        return Err(LibArchiveError::LibArchiveInternalError(LibArchiveInternalStatus::from(r)));
+    }
+
+    Ok(())
+}
+
+fn to_cstring(v: &str) -> LibArchiveResult<CString> {
+    let Ok(v) = CString::new(v) else {
+        return Err(LibArchiveError::NulError);
+    };
+
+    Ok(v)
+}
+
+impl EntryMetadata {
+    /// Metadata for a new entry, e.g. for `ArchiveWriter`, owned by root with mode `0o755` for
+    /// directories and `0o644` otherwise.
+    pub fn new(pathname: &str, entry_type: EntryType) -> EntryMetadata {
+        EntryMetadata {
+            pathname: pathname.to_owned(),
+            entry_type,
+            size: None,
+            mode: if entry_type == EntryType::Directory { 0o755 } else { 0o644 },
+            uid: 0,
+            gid: 0,
+            uname: None,
+            gname: None,
+            mtime: None,
+            mtime_nsec: 0,
+            symlink: None,
+            hardlink: None,
+            is_encrypted: false,
+            xattrs: vec!(),
+            acl: vec!(),
+            acl_text: None,
+        }
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn from_entry(entry: *mut ArchiveEntryStruct) -> LibArchiveResult<EntryMetadata> {
+        if entry.is_null() {
+            return Err(LibArchiveError::Null);
+        }
+
+        let Some(pathname) = convert_c_char_to_string(unsafe { libarchive3_sys::archive_entry_pathname(entry) }) else {
+            return Err(LibArchiveError::FailedGetPathNameFromEntry);
+        };
+
+        let symlink = convert_c_char_to_string(unsafe { libarchive3_sys::archive_entry_symlink(entry) });
+        let hardlink = convert_c_char_to_string(unsafe { libarchive3_sys::archive_entry_hardlink(entry) });
+
+        let filetype = unsafe { libarchive3_sys::archive_entry_filetype(entry) } as u32;
+        let entry_type = if hardlink.is_some() {
+            EntryType::Hardlink
+        } else {
+            match filetype {
+                v if v == libarchive3_sys::AE_IFREG as u32 => EntryType::File,
+                v if v == libarchive3_sys::AE_IFDIR as u32 => EntryType::Directory,
+                v if v == libarchive3_sys::AE_IFLNK as u32 => EntryType::Symlink,
+                v if v == libarchive3_sys::AE_IFCHR as u32 => EntryType::CharacterDevice,
+                v if v == libarchive3_sys::AE_IFBLK as u32 => EntryType::BlockDevice,
+                v if v == libarchive3_sys::AE_IFIFO as u32 => EntryType::Fifo,
+                v if v == libarchive3_sys::AE_IFSOCK as u32 => EntryType::Socket,
+                _ => EntryType::Unknown,
+            }
+        };
+
+        let size = if unsafe { libarchive3_sys::archive_entry_size_is_set(entry) } != 0 {
+            Some(unsafe { libarchive3_sys::archive_entry_size(entry) }.max(0) as u64)
+        } else {
+            None
+        };
+
+        let mtime = if unsafe { libarchive3_sys::archive_entry_mtime_is_set(entry) } != 0 {
+            Some(unsafe { libarchive3_sys::archive_entry_mtime(entry) } as i64)
+        } else {
+            None
+        };
+
+        Ok(EntryMetadata {
+            pathname,
+            entry_type,
+            size,
+            mode: unsafe { libarchive3_sys::archive_entry_perm(entry) } as u32,
+            uid: unsafe { libarchive3_sys::archive_entry_uid(entry) },
+            gid: unsafe { libarchive3_sys::archive_entry_gid(entry) },
+            uname: convert_c_char_to_string(unsafe { libarchive3_sys::archive_entry_uname(entry) }),
+            gname: convert_c_char_to_string(unsafe { libarchive3_sys::archive_entry_gname(entry) }),
+            mtime,
+            mtime_nsec: unsafe { libarchive3_sys::archive_entry_mtime_nsec(entry) } as i64,
+            symlink,
+            hardlink,
+            is_encrypted: unsafe { libarchive3_sys::archive_entry_is_encrypted(entry) } != 0,
+            xattrs: read_xattrs(entry),
+            acl: read_acl(entry),
+            acl_text: read_acl_text(entry),
+        })
+    }
+
+    /// Copies the metadata into a cleared `archive_entry`.
+    ///
+    /// `acl` takes precedence over `acl_text`; the text form is parsed as POSIX.1e first and
+    /// as NFSv4 when that fails.
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn fill_entry(&self, entry: *mut ArchiveEntryStruct) -> LibArchiveResult<()> {
+        let filetype = match self.entry_type {
+            EntryType::Directory => libarchive3_sys::AE_IFDIR,
+            EntryType::Symlink => libarchive3_sys::AE_IFLNK,
+            EntryType::CharacterDevice => libarchive3_sys::AE_IFCHR,
+            EntryType::BlockDevice => libarchive3_sys::AE_IFBLK,
+            EntryType::Fifo => libarchive3_sys::AE_IFIFO,
+            EntryType::Socket => libarchive3_sys::AE_IFSOCK,
+            EntryType::File | EntryType::Hardlink | EntryType::Unknown => libarchive3_sys::AE_IFREG,
+        };
+
+        let pathname = to_cstring(&self.pathname)?;
+        unsafe {
+            libarchive3_sys::archive_entry_set_pathname_utf8(entry, pathname.as_ptr());
+            libarchive3_sys::archive_entry_set_filetype(entry, filetype as _);
+            libarchive3_sys::archive_entry_set_perm(entry, self.mode as _);
+            libarchive3_sys::archive_entry_set_uid(entry, self.uid);
+            libarchive3_sys::archive_entry_set_gid(entry, self.gid);
+        }
+
+        if let Some(size) = self.size {
+            unsafe { libarchive3_sys::archive_entry_set_size(entry, size as i64) };
+        }
+        if let Some(mtime) = self.mtime {
+            unsafe { libarchive3_sys::archive_entry_set_mtime(entry, mtime as _, self.mtime_nsec as _) };
+        }
+        if let Some(uname) = &self.uname {
+            let uname = to_cstring(uname)?;
+            unsafe { libarchive3_sys::archive_entry_copy_uname(entry, uname.as_ptr()) };
+        }
+        if let Some(gname) = &self.gname {
+            let gname = to_cstring(gname)?;
+            unsafe { libarchive3_sys::archive_entry_copy_gname(entry, gname.as_ptr()) };
+        }
+        if let Some(target) = &self.symlink {
+            let target = to_cstring(target)?;
+            unsafe { libarchive3_sys::archive_entry_set_symlink_utf8(entry, target.as_ptr()) };
+        }
+        if let Some(target) = &self.hardlink {
+            let target = to_cstring(target)?;
+            unsafe { libarchive3_sys::archive_entry_set_hardlink_utf8(entry, target.as_ptr()) };
+        }
+
+        for xattr in &self.xattrs {
+            let name = to_cstring(&xattr.name)?;
+            unsafe { libarchive3_sys::archive_entry_xattr_add_entry(entry, name.as_ptr(), xattr.value.as_ptr() as *const c_void, xattr.value.len()) };
+        }
+
+        if !self.acl.is_empty() {
+            for acl in &self.acl {
+                let name = acl.name.as_deref().map(to_cstring).transpose()?;
+                let status_code = unsafe {
+                    libarchive3_sys::archive_entry_acl_add_entry(
+                        entry,
+                        acl.acl_type.to_raw(),
+                        acl.permset as c_int,
+                        acl.tag.to_raw(),
+                        acl.qualifier.unwrap_or(-1) as c_int,
+                        name.as_ref().map_or(std::ptr::null(), |v| v.as_ptr()),
+                    )
+                };
+                if status_code != libarchive3_sys::ARCHIVE_OK {
+                    return Err(LibArchiveError::InvalidAcl(format!("{:?}", acl)));
+                }
+            }
+        } else if let Some(text) = &self.acl_text {
+            let c_text = to_cstring(text)?;
+            let parsed = [libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_ACCESS, libarchive3_sys::ARCHIVE_ENTRY_ACL_TYPE_NFS4].iter().any(|acl_type| {
+                let status_code = unsafe {
+                    libarchive3_sys::archive_entry_acl_clear(entry);
+                    libarchive3_sys::archive_entry_acl_from_text(entry, c_text.as_ptr(), *acl_type)
+                };
+                status_code == libarchive3_sys::ARCHIVE_OK
+            });
+            if !parsed {
+                return Err(LibArchiveError::InvalidAcl(text.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use std::ffi::c_int;
 use libarchive3_sys_by_madosuki as libarchive3_sys;
 use thiserror::Error;
+use crate::FileInfo;
 
 #[derive(Debug)]
 #[repr(i32)]
@@ -93,9 +94,103 @@ pub enum LibArchiveError {
     IsNotExists,
     #[error("Failed uncompress")]
     FailedUncompress,
+    #[error("destination already exists")]
+    DestinationExists,
+    #[error("absolute path rejected")]
+    AbsolutePathRejected,
+    #[error("path containing '..' rejected")]
+    DotDotPathRejected,
+    #[error("path through a symlink rejected")]
+    SymlinkPathRejected,
+    #[error("too many levels of symbolic links")]
+    SymlinkLoop,
     #[error("libarchive internal error: {0}")]
     LibArchiveInternalError(LibArchiveInternalStatus),
+    #[error("invalid transform: {0}")]
+    InvalidTransform(String),
+    #[error("invalid acl: {0}")]
+    InvalidAcl(String),
+    #[error("unsupported format or filter: {0}")]
+    UnsupportedFormat(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("resource limit exceeded")]
+    ResourceLimitExceeded,
+    #[error("cancelled")]
+    Cancelled(Vec<FileInfo>),
+    #[error("libarchive error: {message}")]
+    Archive {
+        status: LibArchiveInternalStatus,
+        errno: i32,
+        message: String,
+    },
 }
 
+impl LibArchiveError {
+    /// A stable machine-readable name of the variant, e.g. `destination_exists`.
+    ///
+    /// Codes are never changed or reused, unlike the messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LibArchiveError::Null => "null",
+            LibArchiveError::FailedFreeArchive => "failed_free_archive",
+            LibArchiveError::FailedGeneratePath => "failed_generate_path",
+            LibArchiveError::FailedCloseReadArchive => "failed_close_read_archive",
+            LibArchiveError::FailedFreeReadArchive => "failed_free_read_archive",
+            LibArchiveError::FailedCreateArchiveEntry => "failed_create_archive_entry",
+            LibArchiveError::FailedCreateArchiveEntryAndFailedCloseRead => "failed_create_archive_entry_and_failed_close_read",
+            LibArchiveError::FailedCreateArchive => "failed_create_archive",
+            LibArchiveError::FailedCreateDirectory => "failed_create_directory",
+            LibArchiveError::FailedCreateFile => "failed_create_file",
+            LibArchiveError::FailedWriteFile => "failed_write_file",
+            LibArchiveError::FailedFlushWhenWrite => "failed_flush_when_write",
+            LibArchiveError::FailedGetPathNameFromEntry => "failed_get_pathname_from_entry",
+            LibArchiveError::EntrySizeLessThanOne => "entry_size_less_than_one",
+            LibArchiveError::NulError => "nul_error",
+            LibArchiveError::FailedGetMetaDataFromFile => "failed_get_metadata_from_file",
+            LibArchiveError::FailedGetMetaDataFromDir => "failed_get_metadata_from_dir",
+            LibArchiveError::FailedWriteHeader => "failed_write_header",
+            LibArchiveError::IsNotFile => "is_not_file",
+            LibArchiveError::IsNotDir => "is_not_dir",
+            LibArchiveError::IsNotExists => "is_not_exists",
+            LibArchiveError::FailedUncompress => "failed_uncompress",
+            LibArchiveError::DestinationExists => "destination_exists",
+            LibArchiveError::AbsolutePathRejected => "absolute_path_rejected",
+            LibArchiveError::DotDotPathRejected => "dot_dot_path_rejected",
+            LibArchiveError::SymlinkPathRejected => "symlink_path_rejected",
+            LibArchiveError::SymlinkLoop => "symlink_loop",
+            LibArchiveError::LibArchiveInternalError(_) => "libarchive_internal_error",
+            LibArchiveError::InvalidTransform(_) => "invalid_transform",
+            LibArchiveError::InvalidAcl(_) => "invalid_acl",
+            LibArchiveError::UnsupportedFormat(_) => "unsupported_format",
+            LibArchiveError::Io(_) => "io",
+            LibArchiveError::ResourceLimitExceeded => "resource_limit_exceeded",
+            LibArchiveError::Cancelled(_) => "cancelled",
+            LibArchiveError::Archive { .. } => "archive",
+        }
+    }
+}
+
+/// Serialized as `{"code": ..., "message": ...}`; libarchive errors add `errno`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for LibArchiveError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", self.to_string().trim_end())?;
+        match self {
+            LibArchiveError::Archive { errno, .. } => map.serialize_entry("errno", errno)?,
+            LibArchiveError::Io(e) => {
+                if let Some(errno) = e.raw_os_error() {
+                    map.serialize_entry("errno", &errno)?;
+                }
+            },
+            _ => {},
+        }
+        map.end()
+    }
+}
 
 pub type LibArchiveResult<T> = Result<T, LibArchiveError>;
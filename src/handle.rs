@@ -0,0 +1,378 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::{ArchiveStruct, ArchiveEntryStruct};
+
+use libc::{c_int, c_void};
+use crate::convert_c_char_to_string;
+use crate::error::{LibArchiveError, LibArchiveResult, LibArchiveInternalStatus};
+use crate::owner::{self, OwnerMap};
+
+pub(crate) fn archive_error(archive: *mut ArchiveStruct, status_code: c_int) -> LibArchiveError {
+    let status = LibArchiveInternalStatus::from(status_code);
+    if archive.is_null() {
+        return LibArchiveError::LibArchiveInternalError(status);
+    }
+
+    let errno = unsafe { libarchive3_sys::archive_errno(archive) };
+    match convert_c_char_to_string(unsafe { libarchive3_sys::archive_error_string(archive) }) {
+        Some(message) => LibArchiveError::Archive { status, errno, message },
+        None => LibArchiveError::LibArchiveInternalError(status),
+    }
+}
+
+/// Fails on anything worse than `ARCHIVE_WARN`, and on `ARCHIVE_RETRY`: the call did not
+/// complete, so e.g. no header was read.
+pub(crate) fn check_status(archive: *mut ArchiveStruct, status_code: c_int) -> LibArchiveResult<()> {
+    if status_code < libarchive3_sys::ARCHIVE_WARN || status_code == libarchive3_sys::ARCHIVE_RETRY {
+        return Err(archive_error(archive, status_code));
+    }
+
+    Ok(())
+}
+
+/// Like `check_status`, but hands back the message of an `ARCHIVE_WARN`.
+pub(crate) fn check_warning(archive: *mut ArchiveStruct, status_code: c_int) -> LibArchiveResult<Option<LibArchiveError>> {
+    check_status(archive, status_code)?;
+    if status_code == libarchive3_sys::ARCHIVE_WARN {
+        return Ok(Some(archive_error(archive, status_code)));
+    }
+
+    Ok(None)
+}
+
+/// Owned handle created by `archive_read_new`.
+///
+/// The handle is closed and freed exactly once, either by `close` or when dropped.
+pub struct ReadArchive {
+    archive: *mut ArchiveStruct,
+    /// `ARCHIVE_WARN`s since the last header was read.
+    warnings: std::cell::RefCell<Vec<LibArchiveError>>,
+}
+
+unsafe impl Send for ReadArchive {}
+
+impl ReadArchive {
+    pub fn new() -> LibArchiveResult<ReadArchive> {
+        let archive = unsafe { libarchive3_sys::archive_read_new() };
+        if archive.is_null() {
+            return Err(LibArchiveError::FailedCreateArchive);
+        }
+
+        Ok(ReadArchive { archive, warnings: std::cell::RefCell::new(vec!()) })
+    }
+
+    pub fn as_ptr(&self) -> *mut ArchiveStruct {
+        self.archive
+    }
+
+    pub fn support_all_filter_and_format(&self) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_read_support_filter_all(self.archive) })?;
+        check_status(self.archive, unsafe { libarchive3_sys::archive_read_support_format_all(self.archive) })
+    }
+
+    pub fn open_filename(&self, file_path: &std::ffi::CStr, block_size: usize) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_read_open_filename(self.archive, file_path.as_ptr(), block_size) })
+    }
+
+    /// Opens an archive held in memory.
+    ///
+    /// # Safety
+    ///
+    /// `data` is not copied and must outlive the handle.
+    pub unsafe fn open_memory(&self, data: &[u8]) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_read_open_memory(self.archive, data.as_ptr() as *const c_void, data.len()) })
+    }
+
+    /// Advances to the next header, returning `false` at the end of the archive.
+    pub fn next_header(&self, entry: &mut *mut ArchiveEntryStruct) -> LibArchiveResult<bool> {
+        self.warnings.borrow_mut().clear();
+        let status_code = unsafe { libarchive3_sys::archive_read_next_header(self.archive, entry) };
+        if status_code == libarchive3_sys::ARCHIVE_EOF {
+            return Ok(false);
+        }
+        self.check_read_status(status_code)?;
+
+        Ok(true)
+    }
+
+    /// `check_status` for a read call, keeping an `ARCHIVE_WARN` for `take_warnings`.
+    pub(crate) fn check_read_status(&self, status_code: c_int) -> LibArchiveResult<()> {
+        if let Some(warning) = check_warning(self.archive, status_code)? {
+            self.warnings.borrow_mut().push(warning);
+        }
+
+        Ok(())
+    }
+
+    /// Warnings about the current entry, from its header and the data read so far.
+    pub fn take_warnings(&self) -> Vec<LibArchiveError> {
+        std::mem::take(&mut *self.warnings.borrow_mut())
+    }
+
+    /// Name of the detected format; only known once a header has been read.
+    pub fn format_name(&self) -> Option<String> {
+        convert_c_char_to_string(unsafe { libarchive3_sys::archive_format_name(self.archive) })
+    }
+
+    /// `ARCHIVE_FORMAT_*` code of the detected format; only known once a header has been read.
+    pub fn format_code(&self) -> c_int {
+        unsafe { libarchive3_sys::archive_format(self.archive) }
+    }
+
+    /// `ARCHIVE_FILTER_*` codes of the detected filters, outermost last, without the trailing
+    /// `ARCHIVE_FILTER_NONE`.
+    pub fn filter_codes(&self) -> Vec<c_int> {
+        let count = unsafe { libarchive3_sys::archive_filter_count(self.archive) };
+        (0..count)
+            .map(|i| unsafe { libarchive3_sys::archive_filter_code(self.archive, i) })
+            .filter(|v| *v != libarchive3_sys::ARCHIVE_FILTER_NONE)
+            .collect()
+    }
+
+    /// Offset of the most recently read header in the uncompressed stream; after the end of
+    /// the archive, the offset of its end marker.
+    pub fn header_position(&self) -> i64 {
+        unsafe { libarchive3_sys::archive_read_header_position(self.archive) }
+    }
+
+    /// Names of the detected filters, outermost last, without the trailing `none`.
+    pub fn filter_names(&self) -> Vec<String> {
+        let count = unsafe { libarchive3_sys::archive_filter_count(self.archive) };
+        (0..count)
+            .filter_map(|i| convert_c_char_to_string(unsafe { libarchive3_sys::archive_filter_name(self.archive, i) }))
+            .filter(|v| v != "none")
+            .collect()
+    }
+
+    pub fn close(mut self) -> LibArchiveResult<()> {
+        let archive = std::mem::replace(&mut self.archive, std::ptr::null_mut());
+
+        let close_status_code = unsafe { libarchive3_sys::archive_read_close(archive) };
+        let free_status_code = unsafe { libarchive3_sys::archive_free(archive) };
+        if close_status_code != libarchive3_sys::ARCHIVE_OK {
+            return Err(LibArchiveError::FailedCloseReadArchive);
+        }
+        if free_status_code != libarchive3_sys::ARCHIVE_OK {
+            return Err(LibArchiveError::FailedFreeReadArchive);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ReadArchive {
+    fn drop(&mut self) {
+        if self.archive.is_null() {
+            return;
+        }
+
+        unsafe {
+            libarchive3_sys::archive_read_close(self.archive);
+            libarchive3_sys::archive_free(self.archive);
+        }
+        self.archive = std::ptr::null_mut();
+    }
+}
+
+fn write_close_and_free(archive: *mut ArchiveStruct) -> LibArchiveResult<()> {
+    let close_status_code = unsafe { libarchive3_sys::archive_write_close(archive) };
+    let close_result = check_status(archive, close_status_code);
+    let free_status_code = unsafe { libarchive3_sys::archive_write_free(archive) };
+    close_result?;
+    if free_status_code != libarchive3_sys::ARCHIVE_OK {
+        return Err(LibArchiveError::FailedFreeArchive);
+    }
+
+    Ok(())
+}
+
+fn write_drop(archive: &mut *mut ArchiveStruct) {
+    if archive.is_null() {
+        return;
+    }
+
+    unsafe {
+        libarchive3_sys::archive_write_close(*archive);
+        libarchive3_sys::archive_write_free(*archive);
+    }
+    *archive = std::ptr::null_mut();
+}
+
+/// Owned handle created by `archive_write_new`.
+pub struct WriteArchive {
+    archive: *mut ArchiveStruct,
+}
+
+unsafe impl Send for WriteArchive {}
+
+impl WriteArchive {
+    pub fn new() -> LibArchiveResult<WriteArchive> {
+        let archive = unsafe { libarchive3_sys::archive_write_new() };
+        if archive.is_null() {
+            return Err(LibArchiveError::FailedCreateArchive);
+        }
+
+        Ok(WriteArchive { archive })
+    }
+
+    pub fn as_ptr(&self) -> *mut ArchiveStruct {
+        self.archive
+    }
+
+    pub fn set_format_by_name(&self, name: &std::ffi::CStr) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_set_format_by_name(self.archive, name.as_ptr()) })
+    }
+
+    pub fn add_filter_by_name(&self, name: &std::ffi::CStr) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_add_filter_by_name(self.archive, name.as_ptr()) })
+    }
+
+    pub fn set_format(&self, format_code: c_int) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_set_format(self.archive, format_code) })
+    }
+
+    /// Filters are applied in the order they are added, the first one next to the format.
+    pub fn add_filter(&self, filter_code: c_int) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_add_filter(self.archive, filter_code) })
+    }
+
+    /// Options in the form taken by `archive_write_set_options`, e.g. `mtree:sha256`.
+    pub fn set_options(&self, options: &std::ffi::CStr) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_set_options(self.archive, options.as_ptr()) })
+    }
+
+    pub fn open_filename(&self, file_path: &std::ffi::CStr) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_open_filename(self.archive, file_path.as_ptr()) })
+    }
+
+    /// Writes to `fd` from its current offset; the descriptor is neither closed nor owned.
+    pub fn open_fd(&self, fd: c_int) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_open_fd(self.archive, fd) })
+    }
+
+    pub fn write_header(&self, entry: &Entry) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_header(self.archive, entry.as_ptr()) })
+    }
+
+    pub fn write_data(&self, data: &[u8]) -> LibArchiveResult<()> {
+        let written = unsafe { libarchive3_sys::archive_write_data(self.archive, data.as_ptr() as *const c_void, data.len()) };
+        if written < 0 {
+            return Err(archive_error(self.archive, written as c_int));
+        }
+
+        Ok(())
+    }
+
+    pub fn finish_entry(&self) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_finish_entry(self.archive) })
+    }
+
+    pub fn close(mut self) -> LibArchiveResult<()> {
+        let archive = std::mem::replace(&mut self.archive, std::ptr::null_mut());
+        write_close_and_free(archive)
+    }
+}
+
+impl Drop for WriteArchive {
+    fn drop(&mut self) {
+        write_drop(&mut self.archive);
+    }
+}
+
+/// Owned handle created by `archive_write_disk_new`.
+pub struct WriteDisk {
+    archive: *mut ArchiveStruct,
+}
+
+unsafe impl Send for WriteDisk {}
+
+impl WriteDisk {
+    pub fn new() -> LibArchiveResult<WriteDisk> {
+        let archive = unsafe { libarchive3_sys::archive_write_disk_new() };
+        if archive.is_null() {
+            return Err(LibArchiveError::FailedCreateArchive);
+        }
+
+        Ok(WriteDisk { archive })
+    }
+
+    pub fn as_ptr(&self) -> *mut ArchiveStruct {
+        self.archive
+    }
+
+    pub fn set_options(&self, flags: c_int) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_disk_set_options(self.archive, flags) })
+    }
+
+    pub fn set_standard_lookup(&self) -> LibArchiveResult<()> {
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_disk_set_standard_lookup(self.archive) })
+    }
+
+    /// Resolves uids and gids through `map` instead of the system user database.
+    pub fn set_owner_lookup(&self, map: &OwnerMap) -> LibArchiveResult<()> {
+        let users = Box::into_raw(Box::new(map.clone())) as *mut c_void;
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_disk_set_user_lookup(self.archive, users, Some(owner::lookup_uid), Some(owner::free_owner_map)) })?;
+
+        let groups = Box::into_raw(Box::new(map.clone())) as *mut c_void;
+        check_status(self.archive, unsafe { libarchive3_sys::archive_write_disk_set_group_lookup(self.archive, groups, Some(owner::lookup_gid), Some(owner::free_owner_map)) })
+    }
+
+    pub fn close(mut self) -> LibArchiveResult<()> {
+        let archive = std::mem::replace(&mut self.archive, std::ptr::null_mut());
+        write_close_and_free(archive)
+    }
+}
+
+impl Drop for WriteDisk {
+    fn drop(&mut self) {
+        write_drop(&mut self.archive);
+    }
+}
+
+/// Owned entry created by `archive_entry_new`.
+pub struct Entry {
+    entry: *mut ArchiveEntryStruct,
+}
+
+unsafe impl Send for Entry {}
+
+impl Entry {
+    pub fn new() -> LibArchiveResult<Entry> {
+        let entry = unsafe { libarchive3_sys::archive_entry_new() };
+        if entry.is_null() {
+            return Err(LibArchiveError::Null);
+        }
+
+        Ok(Entry { entry })
+    }
+
+    pub fn as_ptr(&self) -> *mut ArchiveEntryStruct {
+        self.entry
+    }
+
+    pub fn clear(&mut self) {
+        unsafe { libarchive3_sys::archive_entry_clear(self.entry) };
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        unsafe { libarchive3_sys::archive_entry_free(self.entry) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_status_fails_on_retry() {
+        let archive: *mut ArchiveStruct = std::ptr::null_mut();
+        assert!(check_status(archive, libarchive3_sys::ARCHIVE_OK).is_ok());
+        assert!(check_status(archive, libarchive3_sys::ARCHIVE_WARN).is_ok());
+        assert!(matches!(
+            check_status(archive, libarchive3_sys::ARCHIVE_RETRY),
+            Err(LibArchiveError::LibArchiveInternalError(LibArchiveInternalStatus::ArchiveRetry))
+        ));
+        assert!(check_status(archive, libarchive3_sys::ARCHIVE_FATAL).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveEntryStruct;
+
+use libc::c_void;
+
+use crate::lossy_pathname_from_entry;
+use crate::error::LibArchiveError;
+use crate::handle::{archive_error, ReadArchive};
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TestedEntry {
+    pub pathname: String,
+    /// Bytes decompressed before the entry ended or failed.
+    pub size: u64,
+    pub passed: bool,
+    /// What libarchive reported, including warnings such as a bad CRC.
+    pub error: Option<LibArchiveError>,
+    /// Warnings about the header, e.g. a malformed pax attribute; they fail the entry too.
+    pub warnings: Vec<LibArchiveError>,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TestReport {
+    pub entries: Vec<TestedEntry>,
+    /// Failure outside of any entry, e.g. a truncated or corrupt header.
+    pub error: Option<LibArchiveError>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.entries.iter().all(|v| v.passed)
+    }
+}
+
+/// Decompresses the current entry and drops the data. Returns the byte count and, on failure,
+/// the error together with whether reading can go on.
+fn drain_data(read_archive: &ReadArchive) -> (u64, Option<(LibArchiveError, bool)>) {
+    let archive = read_archive.as_ptr();
+    let mut total = 0_u64;
+    let mut warning: Option<LibArchiveError> = None;
+
+    loop {
+        let mut buf: *mut c_void = std::ptr::null_mut();
+        let mut readed_size = 0_usize;
+        let mut offset = 0_i64;
+        let r = unsafe { libarchive3_sys::archive_read_data_block(archive, &raw mut buf, &mut readed_size, &mut offset) };
+        if r == libarchive3_sys::ARCHIVE_EOF {
+            break;
+        }
+        // `ARCHIVE_FAILED`, e.g. an encrypted entry or an unsupported compression method,
+        // only fails this entry; the next header can still be read.
+        if r < libarchive3_sys::ARCHIVE_WARN || r == libarchive3_sys::ARCHIVE_RETRY {
+            return (total, Some((archive_error(archive, r), r != libarchive3_sys::ARCHIVE_FATAL)));
+        }
+        if r == libarchive3_sys::ARCHIVE_WARN && warning.is_none() {
+            warning = Some(archive_error(archive, r));
+        }
+        total += readed_size as u64;
+    }
+
+    (total, warning.map(|v| (v, true)))
+}
+
+pub(crate) fn test_entries(read_archive: &ReadArchive) -> TestReport {
+    let mut report = TestReport::default();
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    loop {
+        match read_archive.next_header(&mut entry) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(e) => {
+                report.error = Some(e);
+                break;
+            }
+        }
+
+        let pathname = lossy_pathname_from_entry(entry);
+        let warnings = read_archive.take_warnings();
+        let (size, failure) = drain_data(read_archive);
+        let (error, fatal) = match failure {
+            Some((e, recoverable)) => (Some(e), !recoverable),
+            None => (None, false),
+        };
+
+        report.entries.push(TestedEntry {
+            pathname,
+            size,
+            passed: error.is_none() && warnings.is_empty(),
+            error,
+            warnings,
+        });
+
+        if fatal {
+            break;
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Archive, ArchiveExt};
+    use crate::entry::{EntryMetadata, EntryType};
+    use crate::tests::TempDir;
+    use crate::writer::{ArchiveFilter, ArchiveFormat, ArchiveWriter};
+
+    /// Replaces the compression method of every local and central directory header.
+    fn set_zip_method(data: &mut [u8], method: u16) {
+        for i in 0..data.len().saturating_sub(12) {
+            let offset = match &data[i..i + 4] {
+                b"PK\x03\x04" => 8,
+                b"PK\x01\x02" => 10,
+                _ => continue,
+            };
+            data[i + offset..i + offset + 2].copy_from_slice(&method.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn failed_entry_does_not_stop_the_test() {
+        let dir = TempDir::new("integrity-failed-entry");
+        let path = dir.0.join("a.zip");
+        let path_str = path.to_str().unwrap();
+
+        let mut writer = ArchiveWriter::create(path_str, ArchiveFormat::Zip, ArchiveFilter::None).unwrap();
+        writer.write_entry(&EntryMetadata::new("a.txt", EntryType::File), b"aaaa").unwrap();
+        writer.write_entry(&EntryMetadata::new("b.txt", EntryType::File), b"bbbb").unwrap();
+        writer.close().unwrap();
+
+        // No reader supports method 97, so reading the data of every entry fails.
+        let mut data = std::fs::read(&path).unwrap();
+        set_zip_method(&mut data, 97);
+        std::fs::write(&path, data).unwrap();
+
+        let report = Archive::new().unwrap().test(path_str).unwrap();
+        assert!(report.error.is_none());
+        assert_eq!(report.entries.len(), 2);
+        assert_eq!(report.entries[1].pathname, "b.txt");
+        assert!(report.entries.iter().all(|v| !v.passed && v.error.is_some()));
+    }
+}
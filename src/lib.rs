@@ -1,35 +1,52 @@
 use libarchive3_sys_by_madosuki as libarchive3_sys;
-pub use libarchive3_sys::{ArchiveStruct, ArchiveEntryStruct};
+use libarchive3_sys::ArchiveEntryStruct;
 
-use libc::{ c_char, c_int, c_void, size_t};
+use libc::c_char;
 pub mod error;
 pub use error::{LibArchiveError, LibArchiveResult, LibArchiveInternalStatus};
-
-/*
-struct CVoidPtrForThreadSafe {
-    ptr_lock: std::sync::Mutex<*mut c_void>,
-}
-unsafe impl Send for CVoidPtrForThreadSafe {}
-unsafe impl Sync for CVoidPtrForThreadSafe {}
-*/
-
-// fn entry_free(entry: *mut ArchiveEntryStruct) {
-//     unsafe { libarchive3_sys::archive_entry_free(entry); }
-// }
-
-fn set_all_filter_and_format(archive: *mut ArchiveStruct) -> LibArchiveResult<()> {
-    let read_support_filter_all_result = unsafe { libarchive3_sys::archive_read_support_filter_all(archive) };
-    if read_support_filter_all_result != 0 {
-        return Err(LibArchiveError::LibArchiveInternalError(LibArchiveInternalStatus::from(read_support_filter_all_result)));
-    }
-
-    let read_support_format_all_result = unsafe { libarchive3_sys::archive_read_support_format_all(archive) };
-    if read_support_format_all_result != 0 {
-        return Err(LibArchiveError::LibArchiveInternalError(LibArchiveInternalStatus::from(read_support_format_all_result)));
-    }
-    
-    Ok(())
-}
+mod handle;
+use handle::ReadArchive;
+pub mod entry;
+pub use entry::{AclEntry, AclTag, AclType, EntryMetadata, EntryType, FailedEntry, Xattr};
+pub mod options;
+pub use options::{ExtractOptions, OverwritePolicy, ExtractAction};
+pub mod plan;
+pub use plan::{ExtractPlan, PlannedEntry};
+pub mod cancel;
+pub use cancel::CancellationToken;
+pub mod progress;
+pub use progress::{Progress, ProgressHandler};
+pub mod sink;
+pub use sink::{ExtractSink, SinkEntry, MemorySink, DiskSink};
+pub mod vfs;
+pub use vfs::{ArchiveFs, ArchiveFile, DirEntry};
+pub mod checksum;
+pub use checksum::{ChecksumAlgorithm, Checksums};
+pub mod integrity;
+pub use integrity::{TestReport, TestedEntry};
+pub mod owner;
+pub use owner::OwnerMap;
+pub mod writer;
+pub use writer::{ArchiveFilter, ArchiveFormat, ArchiveWriter};
+pub mod links;
+pub use links::Link;
+pub mod summary;
+pub use summary::ArchiveSummary;
+pub mod convert;
+pub use convert::{ConvertReport, ConvertedEntry, LostMetadata};
+pub mod edit;
+pub use edit::{ArchiveEdit, EditReport, EntryPredicate};
+pub mod diff;
+pub use diff::{ArchiveDiff, ChangedEntry, MetadataChange};
+pub mod verify;
+pub use verify::VerifyReport;
+mod manifest;
+mod nested;
+pub mod rewrite;
+pub use rewrite::{PathRewriter, TransformRule};
+pub use nested::DEFAULT_MAX_NESTED_ARCHIVE_SIZE;
+#[cfg(feature = "raw")]
+pub mod raw;
 
 fn convert_c_char_to_string(data: *const c_char) -> Option<String> {
     if data.is_null() {
@@ -59,159 +76,153 @@ fn get_pathname_from_entry(entry: *mut ArchiveEntryStruct) -> LibArchiveResult<S
     }
 }
 
-fn read_and_write_data(archive: *mut ArchiveStruct, archive_write: *mut ArchiveStruct) -> LibArchiveResult<()> {
-    let mut offset = 0 as i64;
+/// The pathname with invalid UTF-8 replaced, to report an entry whose header failed.
+fn lossy_pathname_from_entry(entry: *mut ArchiveEntryStruct) -> String {
+    let pathname = unsafe { libarchive3_sys::archive_entry_pathname(entry) };
+    if pathname.is_null() {
+        return String::new();
+    }
 
-    loop {
-        let buf: *mut c_void = std::ptr::null_mut();
-        
-        let mut readed_size = 0 as size_t;
-        let r = unsafe { libarchive3_sys::archive_read_data_block(archive, &buf, &mut readed_size, &mut offset) };
-        if r == 1 {
-            break;
-        }
+    unsafe { std::ffi::CStr::from_ptr(pathname) }.to_string_lossy().into_owned()
+}
 
-        let write_dta_block_result = unsafe { libarchive3_sys::archive_write_data_block(archive_write, buf as *const c_void, readed_size, offset) };
-        if write_dta_block_result == -1 {
-            return Err(LibArchiveError::FailedWriteFile);
-        }
+fn check_entry_path(name: &str, options: &ExtractOptions) -> LibArchiveResult<()> {
+    let path = std::path::Path::new(name);
+    if options.is_secure_noabsolute() && (path.has_root() || path.is_absolute()) {
+        return Err(LibArchiveError::AbsolutePathRejected);
+    }
+    if options.is_secure_nodotdot() && path.components().any(|c| c == std::path::Component::ParentDir) {
+        return Err(LibArchiveError::DotDotPathRejected);
     }
 
     Ok(())
 }
 
-// fn read_data_with_mutex(archive: *mut ArchiveStruct) -> LibArchiveResult<Vec<u8>> {
-//     let mut offset = 0 as i64;
-//     let mut result: Vec<u8> = vec!();
-
-//     loop {
-//         let tmp: *mut c_void = std::ptr::null_mut();
-//         let safe_ptr = CVoidPtrForThreadSafe{ ptr_lock: std::sync::Mutex::new(tmp) };
-//         let mut readed_size = 0 as usize;
-//         let r = unsafe { libarchive3_sys::archive_read_data_block(archive, &*safe_ptr.ptr_lock.lock().unwrap(), &mut readed_size, &mut offset) };
-
-//         if r == 1 {
-//             break;
-//         }
-
-//         let for_safe: &[u8] = unsafe { std::slice::from_raw_parts(*safe_ptr.ptr_lock.lock().unwrap() as *mut u8, readed_size) };
-//         result.append(&mut for_safe.to_vec());
-
-//         if r == 0 {
-//             continue;
-//         }
-//     }
-
-//     Ok(result)
-// }
+fn join_to_cstring(dir_path: &std::path::Path, name: &str) -> LibArchiveResult<std::ffi::CString> {
+    let out_path = dir_path.join(name);
+    let Some(path_str) = out_path.as_path().to_str() else {
+        return Err(LibArchiveError::FailedGeneratePath);
+    };
+    let Ok(path_with_terminate) = std::ffi::CString::new(path_str) else {
+        return Err(LibArchiveError::FailedGeneratePath);
+    };
 
+    Ok(path_with_terminate)
+}
 
-#[allow(invalid_null_arguments)]
-fn read_data(archive: *mut ArchiveStruct) -> LibArchiveResult<Vec<u8>> {
-    let mut offset = 0 as i64;
-    let mut result: Vec<u8> = vec!();
+/// The first free `name (N).ext` next to `name`; `taken` tells whether a candidate is in use.
+fn keep_both_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    let path = std::path::Path::new(name);
+    let stem = path.file_stem().map(|v| v.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|v| format!(".{}", v.to_string_lossy()));
 
+    let mut index = 1;
     loop {
-        let tmp: *mut c_void = std::ptr::null_mut();
-        let mut readed_size = 0 as usize;
-        let r = unsafe { libarchive3_sys::archive_read_data_block(archive, &tmp, &mut readed_size, &mut offset) };
-
-        if r == 1 || tmp.is_null() {
-            break;
+        let file_name = format!("{} ({}){}", stem, index, extension.as_deref().unwrap_or(""));
+        let candidate = path.with_file_name(file_name).to_string_lossy().into_owned();
+        if !taken(&candidate) {
+            return candidate;
         }
+        index += 1;
+    }
+}
 
-        let for_safe: &[u8] = unsafe { std::slice::from_raw_parts(tmp as *mut u8, readed_size) };
-        result.append(&mut for_safe.to_vec());
+/// What is already at the destination of an entry.
+#[derive(Debug, Clone, Copy)]
+struct ExistingDestination {
+    is_dir: bool,
+    mtime: Option<i64>,
+}
 
-        if r == 0 {
-            continue;
-        }
-    }
+fn existing_on_disk(path: &std::path::Path) -> Option<ExistingDestination> {
+    let existing = std::fs::symlink_metadata(path).ok()?;
+    let mtime = existing.modified().ok()
+        .and_then(|v| v.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|v| v.as_secs() as i64);
 
-    Ok(result)
+    Some(ExistingDestination { is_dir: existing.is_dir(), mtime })
 }
 
-fn read_free(mut _archive: *mut ArchiveStruct) -> LibArchiveResult<()> {
-    let status_code = unsafe { libarchive3_sys::archive_free(_archive) };
-    if status_code != 0 {
-        return Err(LibArchiveError::FailedFreeArchive);
-    }
-    _archive = std::ptr::null_mut();
-    Ok(())
+/// Decides where an entry is written and what that means for an existing destination.
+fn decide_destination(dir_path: &std::path::Path, name: &str, metadata: &EntryMetadata, policy: OverwritePolicy) -> LibArchiveResult<(String, ExtractAction)> {
+    decide_destination_with(name, metadata, policy, |candidate| existing_on_disk(&dir_path.join(candidate)))
 }
 
-fn read_close_and_free(read_archive: *mut ArchiveStruct) -> LibArchiveResult<()> {
-    if read_archive.is_null() {
-        return Ok(());
-    }
-        
-    let close_status_code = unsafe { libarchive3_sys::archive_read_close(read_archive) };
-    if close_status_code != 0 {
-        return Err(LibArchiveError::FailedCloseReadArchive);
-    }
+/// `decide_destination` with `existing` looking up what is at a name below the target
+/// directory.
+fn decide_destination_with(name: &str, metadata: &EntryMetadata, policy: OverwritePolicy, existing: impl Fn(&str) -> Option<ExistingDestination>) -> LibArchiveResult<(String, ExtractAction)> {
+    let Some(found) = existing(name) else {
+        return Ok((name.to_owned(), ExtractAction::Created));
+    };
 
-    read_free(read_archive)?;
+    if metadata.entry_type == EntryType::Directory && found.is_dir {
+        return Ok((name.to_owned(), ExtractAction::Overwritten));
+    }
 
-    Ok(())
+    match policy {
+        OverwritePolicy::Overwrite => Ok((name.to_owned(), ExtractAction::Overwritten)),
+        OverwritePolicy::SkipExisting => Ok((name.to_owned(), ExtractAction::Skipped)),
+        OverwritePolicy::OverwriteIfNewer => {
+            match (metadata.mtime, found.mtime) {
+                (Some(entry_mtime), Some(existing_mtime)) if entry_mtime > existing_mtime => {
+                    Ok((name.to_owned(), ExtractAction::Overwritten))
+                },
+                _ => Ok((name.to_owned(), ExtractAction::Skipped)),
+            }
+        },
+        OverwritePolicy::KeepBoth => {
+            let renamed = keep_both_name(name, |candidate| existing(candidate).is_some());
+            Ok((renamed.clone(), ExtractAction::Renamed(renamed)))
+        },
+        OverwritePolicy::Fail => Err(LibArchiveError::DestinationExists),
+    }
 }
 
-
+/// Upper bound of the read buffer, so that large archives are streamed instead of being
+/// read in one block.
+const MAX_READ_BLOCK_SIZE: usize = 1024 * 1024;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct FileInfo {
     pub file_name: String,
     pub size: usize,
     pub is_success: bool,
     pub error: Option<LibArchiveError>,
+    pub action: Option<ExtractAction>,
+    /// Digests requested through `ExtractOptions::checksum`, for regular files whose data was read.
+    pub checksums: Option<Checksums>,
+    /// Problems that did not fail the entry, e.g. a bad checksum or an owner that could not be
+    /// restored.
+    pub warnings: Vec<LibArchiveError>,
+}
+
+/// The entries of an archive; one whose header cannot be decoded is reported in `failed`
+/// and the listing goes on.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ListReport {
+    pub entries: Vec<EntryMetadata>,
+    pub failed: Vec<FailedEntry>,
 }
 
 #[derive(Debug)]
 pub struct DecompressedData {
-    pub value: Vec<u8>,
+    /// Shared with the links resolved to this entry.
+    pub value: std::sync::Arc<[u8]>,
     pub file_info: FileInfo,
+    pub link: Option<Link>,
+    /// Pathname of the entry whose data `value` holds, when the link was resolved.
+    pub resolved_path: Option<String>,
+    /// The header of the entry, with its xattrs and ACL; `None` when it could not be read.
+    pub metadata: Option<EntryMetadata>,
 }
 
+/// Every operation opens its own read handle, so one `Archive` can be shared between threads.
 pub struct Archive;
 
-pub trait ArchiveExt {
-    fn new() -> LibArchiveResult<Archive>;
-    fn extract_to_memory(&self, file_path: &str) -> LibArchiveResult<Vec<DecompressedData>>;
-    fn get_errno(&self, archive: *mut ArchiveStruct) -> Option<i32>;
-    fn get_error_string(archive: *mut ArchiveStruct) -> Option<String>;
-    fn extract_to_dir(&self, file_path: &str, target_dir_path: &str, flags: Option<i32>) -> LibArchiveResult<Vec<FileInfo>>;
-}
-
-impl ArchiveExt for Archive {
-    fn new() -> LibArchiveResult<Archive> {
-        let archive = unsafe { libarchive3_sys_by_madosuki::archive_read_new() };
-        if archive.is_null() {
-            Err(LibArchiveError::FailedCreateArchive)
-        } else {
-            Ok(Archive)
-        }
-    }
-
-
-    fn get_errno(&self, archive: *mut ArchiveStruct) -> Option<i32> {
-        if archive.is_null() {
-            return None;
-        }
-        
-        unsafe {
-            Some(libarchive3_sys::archive_errno(archive))
-        }
-    }
-
-    fn get_error_string(archive: *mut ArchiveStruct) -> Option<String> {
-        if archive.is_null () {
-            return None;
-        }
-        
-        let bytes = unsafe { libarchive3_sys::archive_error_string(archive) };
-        convert_c_char_to_string(bytes)
-    }
-
-    fn extract_to_memory(&self, file_path: &str) -> LibArchiveResult<Vec<DecompressedData>> {
+impl Archive {
+    fn open_read_archive(&self, file_path: &str) -> LibArchiveResult<(ReadArchive, u64)> {
         let Ok(meta) = std::fs::metadata(file_path) else {
             return Err(LibArchiveError::FailedGetMetaDataFromFile);
         };
@@ -224,140 +235,125 @@ impl ArchiveExt for Archive {
             return Err(LibArchiveError::NulError);
         };
 
+        let read_archive = ReadArchive::new()?;
+        read_archive.support_all_filter_and_format()?;
 
-        let archive: *mut ArchiveStruct = unsafe { libarchive3_sys::archive_read_new() };
-        if archive.is_null() {
-            return Err(LibArchiveError::FailedCreateArchive);
-        }
+        let block_size = (meta.len() as usize).min(MAX_READ_BLOCK_SIZE);
+        read_archive.open_filename(&file_path_cstr, block_size)?;
 
-        set_all_filter_and_format(archive)?;
+        Ok((read_archive, meta.len()))
+    }
 
-        let f_size = meta.len() as usize;
-        unsafe {
-            let status_code = libarchive3_sys::archive_read_open_filename(archive, file_path_cstr.as_ptr(), f_size);
-            if status_code != 0 {
-                return Err(LibArchiveError::LibArchiveInternalError(LibArchiveInternalStatus::from(status_code)));
+    fn index(&self, file_path: &str) -> LibArchiveResult<diff::ArchiveIndex> {
+        let (read_archive, _) = self.open_read_archive(file_path)?;
+
+        let index = match diff::index_entries(&read_archive) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = read_archive.close();
+                return Err(e);
             }
         };
-        
-        let mut entry: *mut ArchiveEntryStruct = unsafe { libarchive3_sys::archive_entry_new() };
-        if entry.is_null() {
-            match read_close_and_free(archive) {
-                Ok(_) => {
-                    return Err(LibArchiveError::FailedCreateArchiveEntry);
-                },
-                Err(_) => {
-                    return Err(LibArchiveError::FailedCreateArchiveEntryAndFailedCloseRead);
-                }
+        read_archive.close()?;
+
+        Ok(index)
+    }
+
+    fn manifest(&self, manifest_path: &str) -> LibArchiveResult<std::collections::BTreeMap<String, diff::IndexedEntry>> {
+        let (read_archive, _) = self.open_read_archive(manifest_path)?;
+
+        let manifest = match manifest::manifest_entries(&read_archive) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = read_archive.close();
+                return Err(e);
             }
-        }
+        };
+        read_archive.close()?;
 
-        let mut result: Vec<DecompressedData> = vec!();
-        unsafe {
-            while libarchive3_sys::archive_read_next_header(archive, &mut entry) != 1 {
-                let pathname = libarchive3_sys::archive_entry_pathname(entry);
-                if pathname.is_null() {
-                    let file_info = FileInfo {
-                        file_name: "".to_owned(),
-                        size: 0,
-                        is_success: false,
-                        error: Some(LibArchiveError::FailedGetPathNameFromEntry),
-                    };
-
-                    let decompress_data = DecompressedData {
-                        file_info,
-                        value: vec!(),
-                    };
-                    result.push(decompress_data);
-                    
-                    continue;
-                }
+        Ok(manifest)
+    }
+}
 
-                let f_name;
-                match convert_c_char_to_string(pathname) {
-                    Some(n) => {
-                        f_name = n;
-                    },
-                    _ => {
-                        let file_info = FileInfo {
-                            file_name: "".to_owned(),
-                            size: 0,
-                            is_success: false,
-                            error: Some(LibArchiveError::FailedGetPathNameFromEntry),
-                        };
-
-                        let decompress_data = DecompressedData {
-                            file_info,
-                            value: vec!(),
-                        };
-                        result.push(decompress_data);
-
-                        continue;
-                    },
-                }
-                
-                let entry_size = libarchive3_sys::archive_entry_size(entry);
-                if entry_size < 1 {
-                    let file_info = FileInfo {
-                        file_name: f_name,
-                        size: 0,
-                        is_success: false,
-                        error: Some(LibArchiveError::EntrySizeLessThanOne),
-                    };
-
-                    let decompress_data = DecompressedData {
-                        file_info,
-                        value: vec!(),
-                    };
-                    result.push(decompress_data);
-                    
-                    continue;
-                }
+pub trait ArchiveExt {
+    #[allow(clippy::new_ret_no_self)]
+    fn new() -> LibArchiveResult<Archive>;
+    fn list(&self, file_path: &str) -> LibArchiveResult<ListReport>;
+    fn extract_to_memory(&self, file_path: &str, options: &ExtractOptions) -> LibArchiveResult<Vec<DecompressedData>>;
+    fn extract_to_dir(&self, file_path: &str, target_dir_path: &str, options: &ExtractOptions) -> LibArchiveResult<Vec<FileInfo>>;
+    fn extract_to_sink(&self, file_path: &str, sink: &mut dyn ExtractSink, options: &ExtractOptions) -> LibArchiveResult<Vec<FileInfo>>;
+    fn dry_run_extract_to_dir(&self, file_path: &str, target_dir_path: &str, options: &ExtractOptions) -> LibArchiveResult<ExtractPlan>;
+    fn open_fs(&self, file_path: &str) -> LibArchiveResult<ArchiveFs>;
+    fn test(&self, file_path: &str) -> LibArchiveResult<TestReport>;
+    fn summary(&self, file_path: &str) -> LibArchiveResult<ArchiveSummary>;
+    fn convert(&self, file_path: &str, target_path: &str, format: ArchiveFormat, filter: ArchiveFilter) -> LibArchiveResult<ConvertReport>;
+    fn edit(&self, file_path: &str, edit: &ArchiveEdit) -> LibArchiveResult<EditReport>;
+    fn diff(&self, old_file_path: &str, new_file_path: &str) -> LibArchiveResult<ArchiveDiff>;
+    fn verify_dir(&self, file_path: &str, dir_path: &str, options: &ExtractOptions) -> LibArchiveResult<VerifyReport>;
+    fn write_manifest(&self, file_path: &str, manifest_path: &str) -> LibArchiveResult<Vec<String>>;
+    fn verify_manifest(&self, file_path: &str, manifest_path: &str) -> LibArchiveResult<VerifyReport>;
+    fn verify_dir_manifest(&self, dir_path: &str, manifest_path: &str) -> LibArchiveResult<VerifyReport>;
+}
 
-                let Ok(readed_data) = read_data(archive) else {
-                    let file_info = FileInfo {
-                        file_name: f_name,
-                        size: 0,
-                        is_success: false,
-                        error: Some(LibArchiveError::FailedUncompress),
-                    };
-
-                    let decompress_data = DecompressedData {
-                        file_info,
-                        value: vec!(),
-                    };
-                    result.push(decompress_data);
-                    
-                    continue;
-                };
-
-                let file_info = FileInfo {
-                    file_name: f_name,
-                    size: entry_size as usize,
-                    is_success: true,
-                    error: None,
-                };
-
-                let tmp = DecompressedData {
-                    file_info,
-                    value: readed_data,
-                };
-                result.push(tmp);
+impl ArchiveExt for Archive {
+    /// Allocates nothing; each operation opens its own handle and reports allocation failures
+    /// itself. Still returns a `Result` so that existing callers keep compiling.
+    fn new() -> LibArchiveResult<Archive> {
+        Ok(Archive)
+    }
+
+
+    fn list(&self, file_path: &str) -> LibArchiveResult<ListReport> {
+        let (read_archive, _) = self.open_read_archive(file_path)?;
+
+        let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+        let mut result = ListReport::default();
+        while read_archive.next_header(&mut entry)? {
+            match EntryMetadata::from_entry(entry) {
+                Ok(v) => result.entries.push(v),
+                Err(error) => result.failed.push(FailedEntry {
+                    pathname: lossy_pathname_from_entry(entry),
+                    error,
+                }),
             }
         }
 
-        match read_close_and_free(archive) {
-            Ok(_) => {
-                return Ok(result);
-            },
+        read_archive.close()?;
+
+        Ok(result)
+    }
+
+    fn extract_to_memory(&self, file_path: &str, options: &ExtractOptions) -> LibArchiveResult<Vec<DecompressedData>> {
+        let (read_archive, archive_size) = self.open_read_archive(file_path)?;
+
+        let mut sink = MemorySink::new();
+        let file_infos = match sink::extract_entries(&read_archive, &mut sink, options, Some(archive_size)) {
+            Ok(v) => v,
             Err(e) => {
-                result.clear();
+                let _ = read_archive.close();
                 return Err(e);
             }
-        }
+        };
+        read_archive.close()?;
+
+        let sink::MemoryParts { values, mut links, mut metadata } = sink.into_parts();
+        let mut values = values.into_iter();
+        let mut result: Vec<DecompressedData> = file_infos.into_iter()
+            .enumerate()
+            .map(|(i, file_info)| DecompressedData {
+                value: std::sync::Arc::from(values.next().unwrap_or_default()),
+                file_info,
+                link: links.remove(&i),
+                resolved_path: None,
+                metadata: metadata.remove(&i),
+            })
+            .collect();
+        links::resolve_links(&mut result, options);
+
+        Ok(result)
     }
 
-    fn extract_to_dir(&self, file_path: &str, target_dir_path: &str, flags: Option<i32>) -> LibArchiveResult<Vec<FileInfo>> {
+    fn extract_to_dir(&self, file_path: &str, target_dir_path: &str, options: &ExtractOptions) -> LibArchiveResult<Vec<FileInfo>> {
         let f_p = std::path::Path::new(file_path);
         if !f_p.exists() {
             return Err(LibArchiveError::IsNotExists);
@@ -376,174 +372,386 @@ impl ArchiveExt for Archive {
         if !dir_path.is_dir() {
             return Err(LibArchiveError::IsNotDir);
         }
-        
-        let Ok(meta) = std::fs::metadata(f_p) else {
-            return Err(LibArchiveError::FailedGetMetaDataFromFile);
+
+        let mut sink = DiskSink::new(dir_path, options)?;
+        let (read_archive, archive_size) = self.open_read_archive(file_path)?;
+
+        let result = match sink::extract_entries(&read_archive, &mut sink, options, Some(archive_size)) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = sink.close();
+                let _ = read_archive.close();
+                return Err(e);
+            }
         };
 
-        let Ok(file_path_cstr) = std::ffi::CString::new(file_path) else {
-            return Err(LibArchiveError::NulError);
+        sink.close()?;
+        read_archive.close()?;
+
+        Ok(result)
+    }
+
+    fn extract_to_sink(&self, file_path: &str, sink: &mut dyn ExtractSink, options: &ExtractOptions) -> LibArchiveResult<Vec<FileInfo>> {
+        let (read_archive, archive_size) = self.open_read_archive(file_path)?;
+
+        let result = match sink::extract_entries(&read_archive, sink, options, Some(archive_size)) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = read_archive.close();
+                return Err(e);
+            }
         };
+        read_archive.close()?;
 
-        let f_size = meta.len() as usize;
-        
-        let archive = unsafe { libarchive3_sys::archive_read_new() };
-        if archive.is_null() {
-            return Err(LibArchiveError::FailedCreateArchive);
-        }
-        set_all_filter_and_format(archive)?;
-        
-        unsafe {
-            let status_code = libarchive3_sys::archive_read_open_filename(archive, file_path_cstr.as_ptr(), f_size);
-            if status_code != 0 {
-                return Err(LibArchiveError::LibArchiveInternalError(LibArchiveInternalStatus::from(status_code)));
+        Ok(result)
+    }
+
+    fn dry_run_extract_to_dir(&self, file_path: &str, target_dir_path: &str, options: &ExtractOptions) -> LibArchiveResult<ExtractPlan> {
+        let mut planner = plan::Planner::new(target_dir_path, options)?;
+        let (read_archive, _) = self.open_read_archive(file_path)?;
+
+        let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+        while read_archive.next_header(&mut entry)? {
+            let mut metadata = match EntryMetadata::from_entry(entry) {
+                Ok(v) => v,
+                Err(e) => {
+                    planner.fail(&lossy_pathname_from_entry(entry), e);
+                    continue;
+                }
+            };
+            if !rewrite::rewrite_entry(&mut metadata, options) {
+                planner.skip(&metadata.pathname);
+                continue;
             }
+            planner.add(&metadata);
+        }
+
+        read_archive.close()?;
+
+        Ok(planner.finish())
+    }
+
+    fn open_fs(&self, file_path: &str) -> LibArchiveResult<ArchiveFs> {
+        let mut sink = vfs::FsSink::default();
+        let file_infos = self.extract_to_sink(file_path, &mut sink, &ExtractOptions::new())?;
+
+        Ok(sink.into_fs(file_infos))
+    }
+
+    /// Decompresses every entry without writing it anywhere, checking the format's own CRCs
+    /// and detecting truncation. Every problem, including an archive that cannot be opened, is
+    /// reported in the `TestReport`.
+    fn test(&self, file_path: &str) -> LibArchiveResult<TestReport> {
+        let read_archive = match self.open_read_archive(file_path) {
+            Ok((v, _)) => v,
+            Err(e) => {
+                return Ok(TestReport {
+                    entries: vec!(),
+                    error: Some(e),
+                });
+            },
         };
 
-        let mut entry: *mut ArchiveEntryStruct = unsafe { libarchive3_sys::archive_entry_new() };
-        if entry.is_null() {
-            match read_close_and_free(archive) {
-                Ok(_) => return Err(LibArchiveError::FailedCreateArchiveEntry),
-                Err(_) => return Err(LibArchiveError::FailedCreateArchiveEntryAndFailedCloseRead)
+        let report = integrity::test_entries(&read_archive);
+        let _ = read_archive.close();
+
+        Ok(report)
+    }
+
+    fn summary(&self, file_path: &str) -> LibArchiveResult<ArchiveSummary> {
+        let (read_archive, archive_size) = self.open_read_archive(file_path)?;
+
+        let summary = match summary::summarize(&read_archive, archive_size) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = read_archive.close();
+                return Err(e);
             }
+        };
+        read_archive.close()?;
 
-        }
+        Ok(summary)
+    }
 
-        let write_disk = unsafe { libarchive3_sys::archive_write_disk_new() };
-        let flags: c_int = match flags {
-            Some(v) => v,
-            _ => {
-                libarchive3_sys::ARCHIVE_EXTRACT_TIME
-                    | libarchive3_sys::ARCHIVE_EXTRACT_PERM
-                    | libarchive3_sys::ARCHIVE_EXTRACT_ACL
-                    | libarchive3_sys::ARCHIVE_EXTRACT_FFLAGS
+    /// Writes the entries of `file_path` into a new archive at `target_path`, reporting the
+    /// metadata the target format cannot hold. The target is removed when conversion fails.
+    fn convert(&self, file_path: &str, target_path: &str, format: ArchiveFormat, filter: ArchiveFilter) -> LibArchiveResult<ConvertReport> {
+        let (read_archive, _) = self.open_read_archive(file_path)?;
+        let write_archive = match writer::open_write_archive(target_path, format, filter) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = read_archive.close();
+                return Err(e);
             }
         };
-        
-        unsafe {
-            libarchive3_sys::archive_write_disk_set_options(write_disk, flags);
-            libarchive3_sys::archive_write_disk_set_standard_lookup(write_disk);
+
+        let report = match convert::convert_entries(&read_archive, &write_archive, format) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = write_archive.close();
+                let _ = read_archive.close();
+                let _ = std::fs::remove_file(target_path);
+                return Err(e);
+            }
+        };
+        read_archive.close()?;
+        if let Err(e) = write_archive.close() {
+            let _ = std::fs::remove_file(target_path);
+            return Err(e);
         }
 
-        let mut result: Vec<FileInfo> = vec!();
-        unsafe {
-            while libarchive3_sys::archive_read_next_header(archive, &mut entry) != 1 {
-                let f_name;
-                match get_pathname_from_entry(entry) {
-                    Ok(name) => {
-                        f_name = name;
-                    },
-                    Err(e) => {
-                        let file_info = FileInfo {
-                            file_name: "".to_owned(),
-                            size: 0,
-                            is_success: false,
-                            error: Some(e),
-                        };
-                        result.push(file_info);
-
-                        continue;
-                    }
+        Ok(report)
+    }
+
+    /// Removes or replaces entries by streaming the archive into a temporary file in the same
+    /// format, which atomically replaces the original on success.
+    /// Reads the archive twice when `edit` removes entries, first to find the hardlinks that
+    /// have to be kept.
+    fn edit(&self, file_path: &str, edit: &ArchiveEdit) -> LibArchiveResult<EditReport> {
+        let links = if edit.removes_entries() {
+            let (read_archive, _) = self.open_read_archive(file_path)?;
+            let links = match edit::plan_links(&read_archive, edit) {
+                Ok(v) => v,
+                Err(e) => {
+                    let _ = read_archive.close();
+                    return Err(e);
                 }
+            };
+            read_archive.close()?;
+            links
+        } else {
+            edit::LinkPlan::default()
+        };
 
-                let out_path = dir_path.join(&f_name);
-                let Some(path_str) = out_path.as_path().to_str() else {
-                    let _file_info = FileInfo {
-                        file_name: f_name,
-                        size: 0,
-                        is_success: false,
-                        error: Some(LibArchiveError::FailedGeneratePath),
-                    };
-                    result.push(_file_info);
-                    
-                    continue;
-                };
-                let Ok(path_with_terminate) = std::ffi::CString::new(path_str) else {
-                    let file_info = FileInfo {
-                        file_name: f_name,
-                        size: 0,
-                        is_success: false,
-                        error: Some(LibArchiveError::FailedGeneratePath),
-                    };
-                    result.push(file_info);
-                    
-                    continue;
-                };
-
-                libarchive3_sys::archive_entry_set_pathname_utf8(entry, path_with_terminate.as_ptr());
-                let status_code = libarchive3_sys::archive_write_header(write_disk, entry);
-                if status_code != 0 {
-                    let _ = libarchive3_sys::archive_write_finish_entry(write_disk);
-                    
-                    let file_info = FileInfo {
-                        file_name: f_name,
-                        size: 0,
-                        is_success: false,
-                        error: Some(LibArchiveError::FailedWriteHeader),
-                    };
-                    result.push(file_info);
+        let (read_archive, _) = self.open_read_archive(file_path)?;
+        let report = match edit::edit_entries(&read_archive, std::path::Path::new(file_path), edit, &links) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = read_archive.close();
+                return Err(e);
+            }
+        };
+        read_archive.close()?;
 
-                    continue;
-                }
-                
-                let entry_size = libarchive3_sys::archive_entry_size(entry);
-                if entry_size < 1 {
-                    let _ = libarchive3_sys::archive_write_finish_entry(write_disk);
-                    
-                    let _file_info = FileInfo {
-                        file_name: f_name,
-                        size: entry_size as usize,
-                        is_success: false,
-                        error: Some(LibArchiveError::EntrySizeLessThanOne),
-                    };
-                    result.push(_file_info);
+        Ok(report)
+    }
 
-                    continue;
-                }
+    /// Compares two archives of any format entry by entry, hashing the data of every file.
+    fn diff(&self, old_file_path: &str, new_file_path: &str) -> LibArchiveResult<ArchiveDiff> {
+        let old = self.index(old_file_path)?;
+        let new = self.index(new_file_path)?;
 
-                let _write_error = match read_and_write_data(archive, write_disk) {
-                    Ok(_) => {
-                        None
-                    },
-                    Err(e) => {
-                        Some(e)
-                    }
-                };
-                
-                let _ = libarchive3_sys::archive_write_finish_entry(write_disk);
-                
-                let _file_info = FileInfo {
-                    file_name: f_name,
-                    size: entry_size as usize,
-                    is_success: true,
-                    error: _write_error,
-                };
-                        
-                result.push(_file_info);
+        let mut diff = diff::diff_indexes(&old.entries, &new.entries);
+        diff.unreadable = old.unreadable.into_iter().chain(new.unreadable).collect();
 
-            }
-        }
+        Ok(diff)
+    }
 
-        let mut status = unsafe { libarchive3_sys::archive_write_close(write_disk) };
-        if status != 0 {
-            return Err(LibArchiveError::LibArchiveInternalError(LibArchiveInternalStatus::from(status)));
-        }
-        status = unsafe { libarchive3_sys::archive_write_free(write_disk) };
-        if status != 0 {
-            return Err(LibArchiveError::LibArchiveInternalError(LibArchiveInternalStatus::from(status)));
+    /// Checks that `dir_path` holds what `extract_to_dir` with the same `options` writes for
+    /// the archive: pathname options decide where each entry is expected, and mode, owner and
+    /// mtime are only compared when `perm`, `owner` and `time` are set. Entries of nested
+    /// archives are not expected.
+    fn verify_dir(&self, file_path: &str, dir_path: &str, options: &ExtractOptions) -> LibArchiveResult<VerifyReport> {
+        let dir_path = std::path::Path::new(dir_path);
+        if !dir_path.is_dir() {
+            return Err(LibArchiveError::IsNotDir);
         }
 
-        match read_close_and_free(archive) {
-            Ok(_) => {
-                return Ok(result);
-            },
+        let index = self.index(file_path)?;
+        let expected = verify::expected_entries(index.entries, options);
+
+        let mut report = verify::verify_entries(&expected, dir_path, options)?;
+        report.unreadable = index.unreadable;
+
+        Ok(report)
+    }
+
+    /// Writes an mtree manifest of the archive with the `type`, `link`, `size`, `mode`, `uid`,
+    /// `time` and `sha256` keywords. Reads the archive twice, first to find the targets of its
+    /// hardlinks. Entries whose header could not be decoded are left out, and their lossily
+    /// decoded pathnames returned.
+    fn write_manifest(&self, file_path: &str, manifest_path: &str) -> LibArchiveResult<Vec<String>> {
+        let (read_archive, _) = self.open_read_archive(file_path)?;
+        let targets = match manifest::hardlink_targets(&read_archive) {
+            Ok(v) => v,
             Err(e) => {
-                result.clear();
+                let _ = read_archive.close();
                 return Err(e);
             }
+        };
+        read_archive.close()?;
+
+        let (read_archive, _) = self.open_read_archive(file_path)?;
+        let write_archive = match manifest::open_manifest_writer(manifest_path) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = read_archive.close();
+                return Err(e);
+            }
+        };
+
+        let unreadable = match manifest::write_manifest(&read_archive, &write_archive, &targets) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = write_archive.close();
+                let _ = read_archive.close();
+                let _ = std::fs::remove_file(manifest_path);
+                return Err(e);
+            }
+        };
+        read_archive.close()?;
+        if let Err(e) = write_archive.close() {
+            let _ = std::fs::remove_file(manifest_path);
+            return Err(e);
+        }
+
+        Ok(unreadable)
+    }
+
+    /// Compares the archive with an mtree manifest, such as one from `write_manifest`.
+    fn verify_manifest(&self, file_path: &str, manifest_path: &str) -> LibArchiveResult<VerifyReport> {
+        let manifest = self.manifest(manifest_path)?;
+        let found = self.index(file_path)?;
+
+        let mut report = manifest::verify_against_manifest(&manifest, &found.entries);
+        report.unreadable = found.unreadable;
+
+        Ok(report)
+    }
+
+    /// Compares the tree below `dir_path` with an mtree manifest, such as one written by
+    /// `write_manifest` for the archive it was extracted from.
+    fn verify_dir_manifest(&self, dir_path: &str, manifest_path: &str) -> LibArchiveResult<VerifyReport> {
+        let dir_path = std::path::Path::new(dir_path);
+        if !dir_path.is_dir() {
+            return Err(LibArchiveError::IsNotDir);
         }
+
+        let manifest = self.manifest(manifest_path)?;
+        let found = verify::index_dir(dir_path)?;
+
+        Ok(manifest::verify_against_manifest(&manifest, &found))
     }
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
 
+    /// A fresh directory below the system temp dir, removed on drop.
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("libarchive-extractor-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn touch(&self, name: &str) {
+            let path = self.0.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn file_with_mtime(name: &str, mtime: Option<i64>) -> EntryMetadata {
+        let mut metadata = EntryMetadata::new(name, EntryType::File);
+        metadata.mtime = mtime;
+        metadata
+    }
+
+    #[test]
+    fn keep_both_name_inserts_counter_before_extension() {
+        let dir = TempDir::new("keep-both-ext");
+        dir.touch("a.txt");
+        assert_eq!(keep_both_name("a.txt", |v| dir.0.join(v).exists()), "a (1).txt");
+    }
+
+    #[test]
+    fn keep_both_name_without_extension() {
+        let dir = TempDir::new("keep-both-noext");
+        dir.touch("README");
+        assert_eq!(keep_both_name("README", |v| dir.0.join(v).exists()), "README (1)");
+    }
+
+    #[test]
+    fn keep_both_name_keeps_dotfile_whole() {
+        let dir = TempDir::new("keep-both-dotfile");
+        dir.touch(".bashrc");
+        assert_eq!(keep_both_name(".bashrc", |v| dir.0.join(v).exists()), ".bashrc (1)");
+    }
+
+    #[test]
+    fn keep_both_name_only_splits_last_extension() {
+        let dir = TempDir::new("keep-both-multi");
+        dir.touch("d/a.tar.gz");
+        assert_eq!(keep_both_name("d/a.tar.gz", |v| dir.0.join(v).exists()), "d/a.tar (1).gz");
+    }
+
+    #[test]
+    fn keep_both_name_skips_taken_names() {
+        let dir = TempDir::new("keep-both-repeat");
+        dir.touch("a.txt");
+        dir.touch("a (1).txt");
+        dir.touch("a (2).txt");
+        assert_eq!(keep_both_name("a.txt", |v| dir.0.join(v).exists()), "a (3).txt");
+    }
+
+    #[test]
+    fn decide_destination_creates_missing_path() {
+        let dir = TempDir::new("decide-missing");
+        let metadata = file_with_mtime("a.txt", None);
+        let (name, action) = decide_destination(&dir.0, "a.txt", &metadata, OverwritePolicy::Fail).unwrap();
+        assert_eq!(name, "a.txt");
+        assert_eq!(action, ExtractAction::Created);
+    }
+
+    #[test]
+    fn decide_destination_applies_policy_to_existing_file() {
+        let dir = TempDir::new("decide-policy");
+        dir.touch("a.txt");
+        let metadata = file_with_mtime("a.txt", None);
+        let decide = |policy| decide_destination(&dir.0, "a.txt", &metadata, policy);
+
+        assert_eq!(decide(OverwritePolicy::Overwrite).unwrap().1, ExtractAction::Overwritten);
+        assert_eq!(decide(OverwritePolicy::SkipExisting).unwrap().1, ExtractAction::Skipped);
+        assert_eq!(
+            decide(OverwritePolicy::KeepBoth).unwrap(),
+            ("a (1).txt".to_owned(), ExtractAction::Renamed("a (1).txt".to_owned())),
+        );
+        assert!(matches!(decide(OverwritePolicy::Fail), Err(LibArchiveError::DestinationExists)));
+    }
+
+    #[test]
+    fn decide_destination_overwrites_only_older_files_when_newer() {
+        let dir = TempDir::new("decide-newer");
+        dir.touch("a.txt");
+        let existing = std::fs::metadata(dir.0.join("a.txt")).unwrap().modified().unwrap()
+            .duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        let decide = |mtime| decide_destination(&dir.0, "a.txt", &file_with_mtime("a.txt", mtime), OverwritePolicy::OverwriteIfNewer).unwrap().1;
+
+        assert_eq!(decide(Some(existing + 60)), ExtractAction::Overwritten);
+        assert_eq!(decide(Some(existing - 60)), ExtractAction::Skipped);
+        assert_eq!(decide(None), ExtractAction::Skipped);
+    }
+
+    #[test]
+    fn decide_destination_merges_into_existing_directory() {
+        let dir = TempDir::new("decide-dir");
+        std::fs::create_dir(dir.0.join("d")).unwrap();
+        let metadata = EntryMetadata::new("d", EntryType::Directory);
+        let (name, action) = decide_destination(&dir.0, "d", &metadata, OverwritePolicy::Fail).unwrap();
+        assert_eq!(name, "d");
+        assert_eq!(action, ExtractAction::Overwritten);
+    }
+}
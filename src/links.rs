@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use crate::DecompressedData;
+use crate::error::LibArchiveError;
+use crate::options::ExtractOptions;
+use crate::vfs::{normalize, parent_of, MAX_SYMLINK_DEPTH};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Link {
+    Symlink(String),
+    Hardlink(String),
+}
+
+enum Resolved {
+    Found(usize),
+    Missing,
+    Loop,
+}
+
+/// Follows links from `index` until an entry that is not a link is found.
+///
+/// Hardlink targets are archive pathnames, symlink targets are relative to the directory of
+/// the link; absolute symlink targets are taken relative to the archive root.
+fn resolve(entries: &[DecompressedData], paths: &HashMap<String, usize>, index: usize, follow_symlinks: bool) -> Resolved {
+    let mut current = index;
+    for _ in 0..MAX_SYMLINK_DEPTH {
+        let target = match &entries[current].link {
+            Some(Link::Hardlink(target)) => normalize(target),
+            Some(Link::Symlink(target)) if follow_symlinks => {
+                let path = normalize(&entries[current].file_info.file_name).unwrap_or_default();
+                if target.starts_with('/') {
+                    normalize(target)
+                } else {
+                    normalize(&format!("{}/{}", parent_of(&path).unwrap_or(""), target))
+                }
+            },
+            _ => return Resolved::Found(current),
+        };
+
+        let Some(next) = target.and_then(|v| paths.get(&v).copied()) else {
+            return Resolved::Missing;
+        };
+        current = next;
+    }
+
+    Resolved::Loop
+}
+
+/// Shares the data of link targets with the links that point at them.
+pub(crate) fn resolve_links(entries: &mut [DecompressedData], options: &ExtractOptions) {
+    let resolve_hardlinks = options.is_resolve_hardlinks();
+    let resolve_symlinks = options.is_resolve_symlinks();
+    if !resolve_hardlinks && !resolve_symlinks {
+        return;
+    }
+
+    let mut paths: HashMap<String, usize> = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if let Some(path) = normalize(&entry.file_info.file_name) {
+            paths.insert(path, i);
+        }
+    }
+
+    for i in 0..entries.len() {
+        let wanted = match &entries[i].link {
+            Some(Link::Hardlink(_)) => resolve_hardlinks,
+            Some(Link::Symlink(_)) => resolve_symlinks,
+            None => false,
+        };
+        if !wanted {
+            continue;
+        }
+
+        match resolve(entries, &paths, i, resolve_symlinks) {
+            Resolved::Found(target) => {
+                if entries[i].value.is_empty() {
+                    entries[i].value = entries[target].value.clone();
+                }
+                entries[i].resolved_path = Some(entries[target].file_info.file_name.clone());
+            },
+            Resolved::Missing => {},
+            Resolved::Loop => {
+                entries[i].file_info.is_success = false;
+                entries[i].file_info.error = Some(LibArchiveError::SymlinkLoop);
+            },
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveEntryStruct;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CString;
+
+use crate::checksum::{ChecksumAlgorithm, EntryHasher};
+use crate::convert::copy_entry;
+use crate::diff::{metadata_changes, ChangedEntry, IndexedEntry};
+use crate::entry::{entry_sha256, set_entry_sha256, EntryMetadata, EntryType};
+use crate::error::{LibArchiveError, LibArchiveResult};
+use crate::handle::{Entry, ReadArchive, WriteArchive};
+use crate::lossy_pathname_from_entry;
+use crate::sink::read_block;
+use crate::verify::{extra_paths, VerifyReport};
+use crate::vfs::normalize;
+use crate::writer::ArchiveFormat;
+
+/// Keywords written for every entry; `type` and `link` tell files, directories and links apart.
+const MTREE_OPTIONS: &str = "!all,type,link,size,mode,uid,time,sha256";
+
+pub(crate) fn open_manifest_writer(manifest_path: &str) -> LibArchiveResult<WriteArchive> {
+    let (Ok(manifest_path), Ok(format_name), Ok(mtree_options)) = (CString::new(manifest_path), CString::new(ArchiveFormat::Mtree.name()), CString::new(MTREE_OPTIONS)) else {
+        return Err(LibArchiveError::NulError);
+    };
+
+    let write_archive = WriteArchive::new()?;
+    write_archive.set_format_by_name(&format_name)?;
+    write_archive.set_options(&mtree_options)?;
+    write_archive.open_filename(&manifest_path)?;
+
+    Ok(write_archive)
+}
+
+/// Normalized pathnames of the entries that hardlinks of `read_archive` point at. Entries
+/// whose header cannot be decoded are skipped; `write_manifest` leaves them out.
+pub(crate) fn hardlink_targets(read_archive: &ReadArchive) -> LibArchiveResult<HashSet<String>> {
+    let mut targets: HashSet<String> = HashSet::new();
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    while read_archive.next_header(&mut entry)? {
+        let Ok(metadata) = EntryMetadata::from_entry(entry) else {
+            continue;
+        };
+        if let Some(target) = metadata.hardlink.as_deref().and_then(normalize) {
+            targets.insert(target);
+        }
+    }
+
+    Ok(targets)
+}
+
+/// The size and sha256 of the data of the current entry, with holes hashed as zeros.
+fn hash_data(read_archive: &ReadArchive, metadata: &EntryMetadata) -> LibArchiveResult<(u64, [u8; 32])> {
+    let mut hasher = EntryHasher::new(&[ChecksumAlgorithm::Sha256]).unwrap_or_default();
+    let mut size = metadata.size.unwrap_or(0);
+    while let Some((offset, block)) = read_block(read_archive)? {
+        hasher.update(offset, block);
+        size = size.max(offset.saturating_add(block.len() as u64));
+    }
+    // A trailing hole of a sparse entry.
+    hasher.update(size, &[]);
+
+    Ok((size, hasher.finish().sha256.unwrap_or_default()))
+}
+
+/// Writes `metadata` as a regular file of `size` bytes without its data; the mtree writer
+/// takes the digest from the entry.
+fn write_digest(write_archive: &WriteArchive, metadata: &EntryMetadata, size: u64, sha256: &[u8; 32]) -> LibArchiveResult<()> {
+    let mut metadata = metadata.clone();
+    metadata.entry_type = EntryType::File;
+    metadata.hardlink = None;
+    metadata.symlink = None;
+    metadata.size = Some(size);
+
+    let entry = Entry::new()?;
+    metadata.fill_entry(entry.as_ptr())?;
+    set_entry_sha256(entry.as_ptr(), sha256)?;
+    write_archive.write_header(&entry)?;
+    write_archive.finish_entry()
+}
+
+/// Writes an mtree line for every entry of `read_archive`, and returns the lossily decoded
+/// pathnames of those left out because their header could not be decoded.
+///
+/// mtree has no hardlinks, and libarchive describes one as an empty file; the size and
+/// digest of the entries in `hardlink_targets` are kept so that their hardlinks are described
+/// as copies instead.
+pub(crate) fn write_manifest(read_archive: &ReadArchive, write_archive: &WriteArchive, hardlink_targets: &HashSet<String>) -> LibArchiveResult<Vec<String>> {
+    let mut targets: HashMap<String, (u64, [u8; 32])> = HashMap::new();
+    let mut unreadable: Vec<String> = vec!();
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    while read_archive.next_header(&mut entry)? {
+        let Ok(metadata) = EntryMetadata::from_entry(entry) else {
+            unreadable.push(lossy_pathname_from_entry(entry));
+            continue;
+        };
+        let pathname = normalize(&metadata.pathname).unwrap_or_default();
+        // The mtree writer describes `./` as `/.`, which its reader cannot parse; the root
+        // is left out of the comparison anyway.
+        if pathname.is_empty() {
+            continue;
+        }
+
+        if let Some((size, sha256)) = metadata.hardlink.as_deref().and_then(normalize).and_then(|v| targets.get(&v)) {
+            write_digest(write_archive, &metadata, *size, sha256)?;
+        } else if metadata.entry_type == EntryType::File && hardlink_targets.contains(&pathname) {
+            let (size, sha256) = hash_data(read_archive, &metadata)?;
+            write_digest(write_archive, &metadata, size, &sha256)?;
+            targets.insert(pathname, (size, sha256));
+        } else if let Some(e) = copy_entry(read_archive, write_archive, entry)? {
+            return Err(e);
+        }
+    }
+
+    Ok(unreadable)
+}
+
+/// Reads the entries of an mtree manifest with their sha256 digests.
+///
+/// libarchive's mtree reader can fill in keywords a line leaves out from the file of that
+/// name, relative to the current directory. Versions with the `mtree:checkfs` option only do
+/// so when it is set, which it is not here; older ones always do.
+pub(crate) fn manifest_entries(read_archive: &ReadArchive) -> LibArchiveResult<BTreeMap<String, IndexedEntry>> {
+    let mut index: BTreeMap<String, IndexedEntry> = BTreeMap::new();
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    while read_archive.next_header(&mut entry)? {
+        if read_archive.format_code() & libarchive3_sys::ARCHIVE_FORMAT_BASE_MASK != libarchive3_sys::ARCHIVE_FORMAT_MTREE {
+            return Err(LibArchiveError::UnsupportedFormat(read_archive.format_name().unwrap_or_default()));
+        }
+
+        let metadata = EntryMetadata::from_entry(entry)?;
+        let sha256 = entry_sha256(entry);
+
+        if let Some(pathname) = normalize(&metadata.pathname).filter(|v| !v.is_empty()) {
+            index.insert(pathname, IndexedEntry { metadata, sha256 });
+        }
+    }
+
+    Ok(index)
+}
+
+/// Compares the entries of `found`, read from an archive or a directory, with a manifest.
+///
+/// Hardlinks are compared as files, and only the keywords of `MTREE_OPTIONS` are compared:
+/// the gid and the owner names are left out.
+pub(crate) fn verify_against_manifest(manifest: &BTreeMap<String, IndexedEntry>, found: &BTreeMap<String, IndexedEntry>) -> VerifyReport {
+    let mut report = VerifyReport::default();
+
+    for (pathname, expected) in manifest {
+        let Some(found_entry) = found.get(pathname) else {
+            report.missing.push(pathname.clone());
+            continue;
+        };
+
+        let mut actual = found_entry.metadata.clone();
+        if actual.entry_type == EntryType::Hardlink {
+            actual.entry_type = EntryType::File;
+            actual.hardlink = None;
+        }
+        actual.uname = None;
+        actual.gname = None;
+        let mut expected_metadata = expected.metadata.clone();
+        expected_metadata.gid = actual.gid;
+        expected_metadata.uname = None;
+        expected_metadata.gname = None;
+
+        let metadata = metadata_changes(&expected_metadata, &actual);
+        let content_changed = match (expected.sha256, found_entry.sha256) {
+            (Some(a), Some(b)) => a != b,
+            // A manifest written without digests still has sizes.
+            (None, _) if actual.entry_type == EntryType::File && expected_metadata.entry_type == EntryType::File => {
+                expected_metadata.size.is_some_and(|v| Some(v) != actual.size)
+            },
+            _ => false,
+        };
+        if content_changed || !metadata.is_empty() {
+            report.mismatched.push(ChangedEntry {
+                pathname: pathname.clone(),
+                content_changed,
+                metadata,
+            });
+        }
+    }
+
+    report.extra = extra_paths(manifest, found.keys());
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Archive, ArchiveExt, ExtractOptions};
+    use crate::entry::{EntryMetadata, EntryType};
+    use crate::tests::TempDir;
+    use crate::writer::{ArchiveFilter, ArchiveFormat, ArchiveWriter};
+
+    /// Replaces the ustar header named `from` with one named `to`, of the same length.
+    fn rename_header(tar: &mut [u8], from: &[u8], to: &[u8]) {
+        let offset = (0..tar.len()).step_by(512).find(|v| tar[*v..].starts_with(from)).unwrap();
+        let header = &mut tar[offset..offset + 512];
+        header[..to.len()].copy_from_slice(to);
+
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u64 = header.iter().map(|v| *v as u64).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    }
+
+    /// A ustar holding an entry whose name is not UTF-8, a file `a` and a hardlink `b` to it.
+    fn undecodable_tar(path: &str) {
+        let mut writer = ArchiveWriter::create(path, ArchiveFormat::Ustar, ArchiveFilter::None).unwrap();
+        writer.write_entry(&EntryMetadata::new("cafe", EntryType::File), b"x").unwrap();
+        writer.write_entry(&EntryMetadata::new("a", EntryType::File), b"a").unwrap();
+        let mut link = EntryMetadata::new("b", EntryType::Hardlink);
+        link.hardlink = Some("a".to_owned());
+        writer.write_entry(&link, b"").unwrap();
+        writer.close().unwrap();
+
+        let mut tar = std::fs::read(path).unwrap();
+        rename_header(&mut tar, b"cafe", b"caf\xe9");
+        std::fs::write(path, tar).unwrap();
+    }
+
+    #[test]
+    fn undecodable_entries_are_left_out() {
+        let dir = TempDir::new("manifest-undecodable");
+        let path = dir.0.join("a.tar");
+        let path_str = path.to_str().unwrap();
+        let manifest_path = dir.0.join("a.mtree");
+        let manifest_str = manifest_path.to_str().unwrap();
+        undecodable_tar(path_str);
+
+        let unreadable = Archive.write_manifest(path_str, manifest_str).unwrap();
+        assert_eq!(unreadable, vec!["caf\u{fffd}"]);
+
+        let manifest = Archive.manifest(manifest_str).unwrap();
+        assert_eq!(manifest.keys().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(manifest["b"].sha256, manifest["a"].sha256);
+    }
+
+    #[test]
+    fn verify_reports_undecodable_entries() {
+        let dir = TempDir::new("manifest-verify-undecodable");
+        let path = dir.0.join("a.tar");
+        let path_str = path.to_str().unwrap();
+        let manifest_path = dir.0.join("a.mtree");
+        let manifest_str = manifest_path.to_str().unwrap();
+        undecodable_tar(path_str);
+        Archive.write_manifest(path_str, manifest_str).unwrap();
+
+        let report = Archive.verify_manifest(path_str, manifest_str).unwrap();
+        assert_eq!(report.unreadable, vec!["caf\u{fffd}"]);
+        assert!(report.missing.is_empty() && report.extra.is_empty() && report.mismatched.is_empty());
+        assert!(!report.passed());
+
+        let extracted = dir.0.join("out");
+        std::fs::create_dir(&extracted).unwrap();
+        let options = ExtractOptions::new();
+        Archive.extract_to_dir(path_str, extracted.to_str().unwrap(), &options).unwrap();
+        let report = Archive.verify_dir(path_str, extracted.to_str().unwrap(), &options).unwrap();
+        assert_eq!(report.unreadable, vec!["caf\u{fffd}"]);
+        assert!(!report.passed());
+    }
+}
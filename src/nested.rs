@@ -0,0 +1,40 @@
+/// Bytes needed to look for every signature in `is_archive_signature`.
+pub(crate) const SNIFF_SIZE: usize = 512;
+
+/// Default upper bound for a nested archive that is buffered in memory before it is opened.
+pub const DEFAULT_MAX_NESTED_ARCHIVE_SIZE: u64 = 256 * 1024 * 1024;
+
+const SIGNATURES: &[&[u8]] = &[
+    b"PK\x03\x04",
+    b"PK\x05\x06",
+    b"PK\x07\x08",
+    b"\x1f\x8b",
+    b"BZh",
+    b"\xfd7zXZ\x00",
+    b"\x28\xb5\x2f\xfd",
+    b"\x04\x22\x4d\x18",
+    b"7z\xbc\xaf\x27\x1c",
+    b"Rar!\x1a\x07",
+    b"!<arch>\n",
+    b"070707",
+    b"070701",
+    b"070702",
+    b"MSCF",
+    b"xar!",
+];
+
+/// Whether `data`, the start of an entry, looks like an archive or a compressed stream.
+///
+/// Only a hint: the entry is still treated as a plain file when libarchive cannot open it.
+pub(crate) fn is_archive_signature(data: &[u8]) -> bool {
+    if SIGNATURES.iter().any(|v| data.starts_with(v)) {
+        return true;
+    }
+
+    data.get(257..262).is_some_and(|v| v == b"ustar")
+}
+
+/// Pathname prefix of the entries of the nested archive stored at `pathname`.
+pub(crate) fn nested_prefix(pathname: &str) -> String {
+    format!("{}!/", pathname)
+}
@@ -0,0 +1,552 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+
+use libc::c_int;
+use std::sync::Arc;
+use crate::cancel::CancellationToken;
+use crate::checksum::ChecksumAlgorithm;
+use crate::nested::DEFAULT_MAX_NESTED_ARCHIVE_SIZE;
+use crate::owner::OwnerMap;
+use crate::progress::{Progress, ProgressHandler, DEFAULT_PROGRESS_INTERVAL};
+use crate::rewrite::{PathRewriter, TransformRule};
+
+/// What `extract_to_dir` does when the destination of an entry already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum OverwritePolicy {
+    #[default]
+    Overwrite,
+    SkipExisting,
+    /// Overwrite only when the entry's mtime is newer than the existing file.
+    OverwriteIfNewer,
+    /// Write the entry next to the existing file as `name (1).ext`.
+    KeepBoth,
+    Fail,
+}
+
+/// What actually happened to an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ExtractAction {
+    Created,
+    Overwritten,
+    Skipped,
+    /// Written under the contained pathname instead of its own.
+    Renamed(String),
+    /// Recognized as a nested archive; its entries follow under `<pathname>!/`.
+    Expanded,
+}
+
+/// Options for `extract_to_dir` and `extract_to_memory`.
+///
+/// The flags are converted to the `ARCHIVE_EXTRACT_*` bitmask of libarchive and only affect
+/// `extract_to_dir`.
+///
+/// The default restores time, permissions, ACLs and file flags, and refuses entries that
+/// escape the target directory through symlinks, `..` or absolute paths.
+#[derive(Clone)]
+pub struct ExtractOptions {
+    owner: bool,
+    perm: bool,
+    time: bool,
+    acl: bool,
+    xattr: bool,
+    fflags: bool,
+    no_overwrite: bool,
+    no_overwrite_newer: bool,
+    unlink: bool,
+    secure_symlinks: bool,
+    secure_nodotdot: bool,
+    secure_noabsolute: bool,
+    sparse: bool,
+    mac_metadata: bool,
+    clear_nochange_fflags: bool,
+    safe_writes: bool,
+    no_autodir: bool,
+    overwrite_policy: OverwritePolicy,
+    progress: Option<ProgressHandler>,
+    progress_interval: u64,
+    cancel_token: Option<Arc<dyn CancellationToken>>,
+    max_depth: usize,
+    max_nested_archive_size: u64,
+    max_total_size: Option<u64>,
+    max_entries: Option<usize>,
+    checksums: Vec<ChecksumAlgorithm>,
+    strip_components: usize,
+    transforms: Vec<TransformRule>,
+    path_rewriter: Option<PathRewriter>,
+    rewrite_link_targets: bool,
+    owner_map: Option<OwnerMap>,
+    resolve_hardlinks: bool,
+    resolve_symlinks: bool,
+}
+
+impl std::fmt::Debug for ExtractOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ExtractOptions")
+            .field("bits", &self.bits())
+            .field("overwrite_policy", &self.overwrite_policy)
+            .field("progress", &self.progress)
+            .field("progress_interval", &self.progress_interval)
+            .field("cancel_token", &self.cancel_token.is_some())
+            .field("max_depth", &self.max_depth)
+            .field("max_nested_archive_size", &self.max_nested_archive_size)
+            .field("max_total_size", &self.max_total_size)
+            .field("max_entries", &self.max_entries)
+            .field("checksums", &self.checksums)
+            .field("strip_components", &self.strip_components)
+            .field("transforms", &self.transforms)
+            .field("path_rewriter", &self.path_rewriter)
+            .field("rewrite_link_targets", &self.rewrite_link_targets)
+            .field("owner_map", &self.owner_map)
+            .field("resolve_hardlinks", &self.resolve_hardlinks)
+            .field("resolve_symlinks", &self.resolve_symlinks)
+            .finish()
+    }
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            owner: false,
+            perm: true,
+            time: true,
+            acl: true,
+            xattr: false,
+            fflags: true,
+            no_overwrite: false,
+            no_overwrite_newer: false,
+            unlink: false,
+            secure_symlinks: true,
+            secure_nodotdot: true,
+            secure_noabsolute: true,
+            sparse: false,
+            mac_metadata: false,
+            clear_nochange_fflags: false,
+            safe_writes: false,
+            no_autodir: false,
+            overwrite_policy: OverwritePolicy::Overwrite,
+            progress: None,
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            cancel_token: None,
+            max_depth: 0,
+            max_nested_archive_size: DEFAULT_MAX_NESTED_ARCHIVE_SIZE,
+            max_total_size: None,
+            max_entries: None,
+            checksums: vec!(),
+            strip_components: 0,
+            transforms: vec!(),
+            path_rewriter: None,
+            rewrite_link_targets: false,
+            owner_map: None,
+            resolve_hardlinks: false,
+            resolve_symlinks: false,
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Options with every flag turned off, matching a bitmask of `0`.
+    pub fn empty() -> Self {
+        ExtractOptions {
+            perm: false,
+            time: false,
+            acl: false,
+            fflags: false,
+            secure_symlinks: false,
+            secure_nodotdot: false,
+            secure_noabsolute: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn owner(mut self, v: bool) -> Self {
+        self.owner = v;
+        self
+    }
+
+    pub fn perm(mut self, v: bool) -> Self {
+        self.perm = v;
+        self
+    }
+
+    pub fn time(mut self, v: bool) -> Self {
+        self.time = v;
+        self
+    }
+
+    pub fn acl(mut self, v: bool) -> Self {
+        self.acl = v;
+        self
+    }
+
+    pub fn xattr(mut self, v: bool) -> Self {
+        self.xattr = v;
+        self
+    }
+
+    pub fn fflags(mut self, v: bool) -> Self {
+        self.fflags = v;
+        self
+    }
+
+    pub fn no_overwrite(mut self, v: bool) -> Self {
+        self.no_overwrite = v;
+        self
+    }
+
+    pub fn no_overwrite_newer(mut self, v: bool) -> Self {
+        self.no_overwrite_newer = v;
+        self
+    }
+
+    pub fn unlink(mut self, v: bool) -> Self {
+        self.unlink = v;
+        self
+    }
+
+    pub fn secure_symlinks(mut self, v: bool) -> Self {
+        self.secure_symlinks = v;
+        self
+    }
+
+    pub fn secure_nodotdot(mut self, v: bool) -> Self {
+        self.secure_nodotdot = v;
+        self
+    }
+
+    pub fn secure_noabsolute(mut self, v: bool) -> Self {
+        self.secure_noabsolute = v;
+        self
+    }
+
+    pub fn sparse(mut self, v: bool) -> Self {
+        self.sparse = v;
+        self
+    }
+
+    pub fn mac_metadata(mut self, v: bool) -> Self {
+        self.mac_metadata = v;
+        self
+    }
+
+    pub fn clear_nochange_fflags(mut self, v: bool) -> Self {
+        self.clear_nochange_fflags = v;
+        self
+    }
+
+    pub fn safe_writes(mut self, v: bool) -> Self {
+        self.safe_writes = v;
+        self
+    }
+
+    pub fn no_autodir(mut self, v: bool) -> Self {
+        self.no_autodir = v;
+        self
+    }
+
+    pub fn overwrite_policy(mut self, v: OverwritePolicy) -> Self {
+        self.overwrite_policy = v;
+        self
+    }
+
+    /// Called when an entry starts, every `progress_interval` decompressed bytes and when the
+    /// entry's data is done.
+    pub fn on_progress<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(ProgressHandler::new(f));
+        self
+    }
+
+    pub fn progress_interval(mut self, bytes: u64) -> Self {
+        self.progress_interval = bytes;
+        self
+    }
+
+    /// Stops the extraction once the token reports cancellation, e.g. an `Arc<AtomicBool>`.
+    pub fn cancel_token(mut self, token: Arc<dyn CancellationToken>) -> Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// Expands entries that are archives themselves, down to `max_depth` levels of nesting.
+    ///
+    /// Nested entries are reported as `outer.zip!/inner.tar.gz!/file.txt`. `0`, the default,
+    /// keeps nested archives as plain files.
+    pub fn recursive(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Nested archives larger than this are kept as plain files. A sparse entry whose hole
+    /// reaches past it while it is checked aborts with `ResourceLimitExceeded`.
+    pub fn max_nested_archive_size(mut self, bytes: u64) -> Self {
+        self.max_nested_archive_size = bytes;
+        self
+    }
+
+    /// Aborts with `ResourceLimitExceeded` once more than `bytes` of entry data are extracted,
+    /// counted across every nesting level, including nested archives buffered in memory and
+    /// the holes of sparse entries.
+    pub fn max_total_size(mut self, bytes: u64) -> Self {
+        self.max_total_size = Some(bytes);
+        self
+    }
+
+    /// Aborts with `ResourceLimitExceeded` once more than `count` entries are read, counted
+    /// across every nesting level.
+    pub fn max_entries(mut self, count: usize) -> Self {
+        self.max_entries = Some(count);
+        self
+    }
+
+    /// Computes `algorithm` over the data of every extracted entry, reported in
+    /// `FileInfo::checksums`.
+    pub fn checksum(mut self, algorithm: ChecksumAlgorithm) -> Self {
+        if !self.checksums.contains(&algorithm) {
+            self.checksums.push(algorithm);
+        }
+        self
+    }
+
+    /// Removes the first `n` components from every pathname, like `tar --strip-components`.
+    /// Entries with nothing left are skipped; hardlink targets are stripped the same way.
+    pub fn strip_components(mut self, n: usize) -> Self {
+        self.strip_components = n;
+        self
+    }
+
+    /// Adds a substitution applied to every pathname after `strip_components`, in the order
+    /// the rules are added.
+    pub fn transform(mut self, rule: TransformRule) -> Self {
+        self.transforms.push(rule);
+        self
+    }
+
+    /// Called with every pathname after the transform rules; returning `None` skips the entry.
+    pub fn rewrite_pathname<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.path_rewriter = Some(PathRewriter::new(f));
+        self
+    }
+
+    /// Also runs symlink and hardlink targets through the transform rules and the rewrite
+    /// callback. Hardlink targets name other entries, so this is usually wanted when entries
+    /// are moved around.
+    pub fn rewrite_link_targets(mut self, v: bool) -> Self {
+        self.rewrite_link_targets = v;
+        self
+    }
+
+    /// Maps the owners of extracted entries instead of looking their names up in the system
+    /// user database. Only used together with `owner(true)`.
+    pub fn owner_map(mut self, map: OwnerMap) -> Self {
+        self.owner_map = Some(map);
+        self
+    }
+
+    /// `extract_to_memory` only: hardlinks get the data of their target.
+    pub fn resolve_hardlinks(mut self, v: bool) -> Self {
+        self.resolve_hardlinks = v;
+        self
+    }
+
+    /// `extract_to_memory` only: symlinks pointing at another entry of the archive get the
+    /// data of that entry; links that loop are reported as `SymlinkLoop`.
+    pub fn resolve_symlinks(mut self, v: bool) -> Self {
+        self.resolve_symlinks = v;
+        self
+    }
+
+    pub(crate) fn is_owner(&self) -> bool {
+        self.owner
+    }
+
+    pub(crate) fn is_perm(&self) -> bool {
+        self.perm
+    }
+
+    pub(crate) fn is_time(&self) -> bool {
+        self.time
+    }
+
+    pub(crate) fn is_resolve_hardlinks(&self) -> bool {
+        self.resolve_hardlinks
+    }
+
+    pub(crate) fn is_resolve_symlinks(&self) -> bool {
+        self.resolve_symlinks
+    }
+
+    pub(crate) fn get_owner_map(&self) -> Option<&OwnerMap> {
+        self.owner_map.as_ref()
+    }
+
+    pub(crate) fn get_transforms(&self) -> &[TransformRule] {
+        &self.transforms
+    }
+
+    pub(crate) fn get_path_rewriter(&self) -> Option<&PathRewriter> {
+        self.path_rewriter.as_ref()
+    }
+
+    pub(crate) fn is_rewrite_link_targets(&self) -> bool {
+        self.rewrite_link_targets
+    }
+
+    pub(crate) fn get_strip_components(&self) -> usize {
+        self.strip_components
+    }
+
+    pub(crate) fn get_checksums(&self) -> &[ChecksumAlgorithm] {
+        &self.checksums
+    }
+
+    pub(crate) fn get_max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub(crate) fn get_max_nested_archive_size(&self) -> u64 {
+        self.max_nested_archive_size
+    }
+
+    pub(crate) fn get_max_total_size(&self) -> Option<u64> {
+        self.max_total_size
+    }
+
+    pub(crate) fn get_max_entries(&self) -> Option<usize> {
+        self.max_entries
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        match &self.cancel_token {
+            Some(token) => token.is_cancelled(),
+            None => false,
+        }
+    }
+
+    pub(crate) fn get_progress(&self) -> (Option<&ProgressHandler>, u64) {
+        (self.progress.as_ref(), self.progress_interval)
+    }
+
+    pub(crate) fn get_overwrite_policy(&self) -> OverwritePolicy {
+        self.overwrite_policy
+    }
+
+    pub(crate) fn is_secure_symlinks(&self) -> bool {
+        self.secure_symlinks
+    }
+
+    pub(crate) fn is_secure_nodotdot(&self) -> bool {
+        self.secure_nodotdot
+    }
+
+    pub(crate) fn is_secure_noabsolute(&self) -> bool {
+        self.secure_noabsolute
+    }
+
+    /// The `ARCHIVE_EXTRACT_*` bitmask for these options.
+    pub fn bits(&self) -> c_int {
+        let flags = [
+            (self.owner, libarchive3_sys::ARCHIVE_EXTRACT_OWNER),
+            (self.perm, libarchive3_sys::ARCHIVE_EXTRACT_PERM),
+            (self.time, libarchive3_sys::ARCHIVE_EXTRACT_TIME),
+            (self.acl, libarchive3_sys::ARCHIVE_EXTRACT_ACL),
+            (self.xattr, libarchive3_sys::ARCHIVE_EXTRACT_XATTR),
+            (self.fflags, libarchive3_sys::ARCHIVE_EXTRACT_FFLAGS),
+            (self.no_overwrite, libarchive3_sys::ARCHIVE_EXTRACT_NO_OVERWRITE),
+            (self.no_overwrite_newer, libarchive3_sys::ARCHIVE_EXTRACT_NO_OVERWRITE_NEWER),
+            (self.unlink, libarchive3_sys::ARCHIVE_EXTRACT_UNLINK),
+            (self.secure_symlinks, libarchive3_sys::ARCHIVE_EXTRACT_SECURE_SYMLINKS),
+            (self.secure_nodotdot, libarchive3_sys::ARCHIVE_EXTRACT_SECURE_NODOTDOT),
+            (self.secure_noabsolute, libarchive3_sys::ARCHIVE_EXTRACT_SECURE_NOABSOLUTEPATHS),
+            (self.sparse, libarchive3_sys::ARCHIVE_EXTRACT_SPARSE),
+            (self.mac_metadata, libarchive3_sys::ARCHIVE_EXTRACT_MAC_METADATA),
+            (self.clear_nochange_fflags, libarchive3_sys::ARCHIVE_EXTRACT_CLEAR_NOCHANGE_FFLAGS),
+            (self.safe_writes, libarchive3_sys::ARCHIVE_EXTRACT_SAFE_WRITES),
+            (self.no_autodir, libarchive3_sys::ARCHIVE_EXTRACT_NO_AUTODIR),
+        ];
+
+        flags.iter()
+            .filter(|(enabled, _)| *enabled)
+            .fold(0, |acc, (_, flag)| acc | flag)
+    }
+
+    /// The bitmask handed to the write-disk handle.
+    ///
+    /// Entries are joined onto the target directory before they are written, so the absolute
+    /// path check is done on the original pathname instead of by libarchive.
+    pub(crate) fn write_disk_flags(&self) -> c_int {
+        self.bits() & !libarchive3_sys::ARCHIVE_EXTRACT_SECURE_NOABSOLUTEPATHS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Setter = fn(ExtractOptions) -> ExtractOptions;
+
+    #[test]
+    fn default_refuses_escaping_entries() {
+        let bits = ExtractOptions::default().bits();
+        let secure = libarchive3_sys::ARCHIVE_EXTRACT_SECURE_SYMLINKS
+            | libarchive3_sys::ARCHIVE_EXTRACT_SECURE_NODOTDOT
+            | libarchive3_sys::ARCHIVE_EXTRACT_SECURE_NOABSOLUTEPATHS;
+        assert_eq!(bits & secure, secure);
+        assert_eq!(bits, secure
+            | libarchive3_sys::ARCHIVE_EXTRACT_PERM
+            | libarchive3_sys::ARCHIVE_EXTRACT_TIME
+            | libarchive3_sys::ARCHIVE_EXTRACT_ACL
+            | libarchive3_sys::ARCHIVE_EXTRACT_FFLAGS);
+    }
+
+    #[test]
+    fn empty_has_no_bits() {
+        assert_eq!(ExtractOptions::empty().bits(), 0);
+    }
+
+    #[test]
+    fn each_flag_maps_to_its_bit() {
+        let cases: [(Setter, c_int); 17] = [
+            (|v| v.owner(true), libarchive3_sys::ARCHIVE_EXTRACT_OWNER),
+            (|v| v.perm(true), libarchive3_sys::ARCHIVE_EXTRACT_PERM),
+            (|v| v.time(true), libarchive3_sys::ARCHIVE_EXTRACT_TIME),
+            (|v| v.acl(true), libarchive3_sys::ARCHIVE_EXTRACT_ACL),
+            (|v| v.xattr(true), libarchive3_sys::ARCHIVE_EXTRACT_XATTR),
+            (|v| v.fflags(true), libarchive3_sys::ARCHIVE_EXTRACT_FFLAGS),
+            (|v| v.no_overwrite(true), libarchive3_sys::ARCHIVE_EXTRACT_NO_OVERWRITE),
+            (|v| v.no_overwrite_newer(true), libarchive3_sys::ARCHIVE_EXTRACT_NO_OVERWRITE_NEWER),
+            (|v| v.unlink(true), libarchive3_sys::ARCHIVE_EXTRACT_UNLINK),
+            (|v| v.secure_symlinks(true), libarchive3_sys::ARCHIVE_EXTRACT_SECURE_SYMLINKS),
+            (|v| v.secure_nodotdot(true), libarchive3_sys::ARCHIVE_EXTRACT_SECURE_NODOTDOT),
+            (|v| v.secure_noabsolute(true), libarchive3_sys::ARCHIVE_EXTRACT_SECURE_NOABSOLUTEPATHS),
+            (|v| v.sparse(true), libarchive3_sys::ARCHIVE_EXTRACT_SPARSE),
+            (|v| v.mac_metadata(true), libarchive3_sys::ARCHIVE_EXTRACT_MAC_METADATA),
+            (|v| v.clear_nochange_fflags(true), libarchive3_sys::ARCHIVE_EXTRACT_CLEAR_NOCHANGE_FFLAGS),
+            (|v| v.safe_writes(true), libarchive3_sys::ARCHIVE_EXTRACT_SAFE_WRITES),
+            (|v| v.no_autodir(true), libarchive3_sys::ARCHIVE_EXTRACT_NO_AUTODIR),
+        ];
+
+        for (set, bit) in cases {
+            assert_eq!(set(ExtractOptions::empty()).bits(), bit);
+        }
+    }
+
+    #[test]
+    fn write_disk_flags_leave_absolute_path_check_to_the_caller() {
+        let options = ExtractOptions::default();
+        assert_eq!(options.write_disk_flags(), options.bits() & !libarchive3_sys::ARCHIVE_EXTRACT_SECURE_NOABSOLUTEPATHS);
+        assert_ne!(options.write_disk_flags() & libarchive3_sys::ARCHIVE_EXTRACT_SECURE_SYMLINKS, 0);
+        assert_ne!(options.write_disk_flags() & libarchive3_sys::ARCHIVE_EXTRACT_SECURE_NODOTDOT, 0);
+    }
+}
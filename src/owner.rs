@@ -0,0 +1,167 @@
+use libc::{c_char, c_void};
+use std::collections::HashMap;
+
+use crate::convert_c_char_to_string;
+
+/// How owners stored in the archive become uids and gids on disk.
+///
+/// Used through `ExtractOptions::owner_map`; ownership is only applied when
+/// `ExtractOptions::owner` is enabled. An id is resolved by the fixed owner first, then by the
+/// name mappings, then by the id mappings, and finally by the name in the system user database
+/// unless `numeric_only` is set. Without any match the id stored in the archive is kept.
+#[derive(Debug, Clone, Default)]
+pub struct OwnerMap {
+    users_by_name: HashMap<String, i64>,
+    users_by_id: HashMap<i64, i64>,
+    groups_by_name: HashMap<String, i64>,
+    groups_by_id: HashMap<i64, i64>,
+    fixed_uid: Option<i64>,
+    fixed_gid: Option<i64>,
+    numeric_only: bool,
+}
+
+impl OwnerMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn map_user_name(mut self, name: &str, uid: i64) -> Self {
+        self.users_by_name.insert(name.to_owned(), uid);
+        self
+    }
+
+    pub fn map_uid(mut self, from: i64, to: i64) -> Self {
+        self.users_by_id.insert(from, to);
+        self
+    }
+
+    pub fn map_group_name(mut self, name: &str, gid: i64) -> Self {
+        self.groups_by_name.insert(name.to_owned(), gid);
+        self
+    }
+
+    pub fn map_gid(mut self, from: i64, to: i64) -> Self {
+        self.groups_by_id.insert(from, to);
+        self
+    }
+
+    /// Gives every entry the same owner, like `tar --owner --group`.
+    pub fn fixed_owner(mut self, uid: i64, gid: i64) -> Self {
+        self.fixed_uid = Some(uid);
+        self.fixed_gid = Some(gid);
+        self
+    }
+
+    /// Ignores user and group names, like `tar --numeric-owner`.
+    pub fn numeric_only(mut self, v: bool) -> Self {
+        self.numeric_only = v;
+        self
+    }
+
+    pub fn resolve_uid(&self, name: Option<&str>, id: i64) -> i64 {
+        resolve(self.fixed_uid, &self.users_by_name, &self.users_by_id, self.numeric_only, name, id, system_uid)
+    }
+
+    pub fn resolve_gid(&self, name: Option<&str>, id: i64) -> i64 {
+        resolve(self.fixed_gid, &self.groups_by_name, &self.groups_by_id, self.numeric_only, name, id, system_gid)
+    }
+}
+
+fn resolve(fixed: Option<i64>, by_name: &HashMap<String, i64>, by_id: &HashMap<i64, i64>, numeric_only: bool, name: Option<&str>, id: i64, system: fn(&str) -> Option<i64>) -> i64 {
+    if let Some(v) = fixed {
+        return v;
+    }
+
+    let name = name.filter(|_| !numeric_only);
+    if let Some(v) = name.and_then(|v| by_name.get(v)) {
+        return *v;
+    }
+    if let Some(v) = by_id.get(&id) {
+        return *v;
+    }
+
+    name.and_then(system).unwrap_or(id)
+}
+
+#[cfg(unix)]
+fn system_uid(name: &str) -> Option<i64> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf: Vec<c_char> = vec![0; 16 * 1024];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let r = unsafe { libc::getpwnam_r(name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if r != 0 || result.is_null() {
+        return None;
+    }
+
+    Some(passwd.pw_uid as i64)
+}
+
+#[cfg(unix)]
+fn system_gid(name: &str) -> Option<i64> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf: Vec<c_char> = vec![0; 16 * 1024];
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let r = unsafe { libc::getgrnam_r(name.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if r != 0 || result.is_null() {
+        return None;
+    }
+
+    Some(group.gr_gid as i64)
+}
+
+/// The name of `uid` in the system user database.
+#[cfg(unix)]
+pub(crate) fn system_user_name(uid: u32) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf: Vec<c_char> = vec![0; 16 * 1024];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let r = unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if r != 0 || result.is_null() {
+        return None;
+    }
+
+    convert_c_char_to_string(passwd.pw_name)
+}
+
+/// The name of `gid` in the system group database.
+#[cfg(unix)]
+pub(crate) fn system_group_name(gid: u32) -> Option<String> {
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf: Vec<c_char> = vec![0; 16 * 1024];
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let r = unsafe { libc::getgrgid_r(gid, &mut group, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if r != 0 || result.is_null() {
+        return None;
+    }
+
+    convert_c_char_to_string(group.gr_name)
+}
+
+#[cfg(not(unix))]
+fn system_uid(_name: &str) -> Option<i64> {
+    None
+}
+
+#[cfg(not(unix))]
+fn system_gid(_name: &str) -> Option<i64> {
+    None
+}
+
+/// Lookup callbacks for `archive_write_disk_set_user_lookup` and
+/// `archive_write_disk_set_group_lookup`; `private_data` is a `Box<OwnerMap>` released by
+/// `free_owner_map`.
+pub(crate) unsafe extern "C" fn lookup_uid(private_data: *mut c_void, name: *const c_char, id: i64) -> i64 {
+    let map = unsafe { &*(private_data as *const OwnerMap) };
+    map.resolve_uid(convert_c_char_to_string(name).as_deref(), id)
+}
+
+pub(crate) unsafe extern "C" fn lookup_gid(private_data: *mut c_void, name: *const c_char, id: i64) -> i64 {
+    let map = unsafe { &*(private_data as *const OwnerMap) };
+    map.resolve_gid(convert_c_char_to_string(name).as_deref(), id)
+}
+
+pub(crate) unsafe extern "C" fn free_owner_map(private_data: *mut c_void) {
+    drop(unsafe { Box::from_raw(private_data as *mut OwnerMap) });
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{check_entry_path, decide_destination_with, existing_on_disk, ExistingDestination};
+use crate::entry::{EntryMetadata, EntryType};
+use crate::error::{LibArchiveError, LibArchiveResult};
+use crate::options::{ExtractAction, ExtractOptions};
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PlannedEntry {
+    pub file_name: String,
+    /// Final on-disk path, `None` when the entry would be rejected.
+    pub destination: Option<PathBuf>,
+    pub action: Option<ExtractAction>,
+    pub would_overwrite: bool,
+    pub rejected: Option<LibArchiveError>,
+    pub size: u64,
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExtractPlan {
+    pub entries: Vec<PlannedEntry>,
+    /// Bytes that would be written for entries that are neither skipped nor rejected.
+    pub total_bytes: u64,
+}
+
+pub(crate) struct Planner<'a> {
+    dir_path: PathBuf,
+    options: &'a ExtractOptions,
+    planned_symlinks: Vec<PathBuf>,
+    /// What earlier entries of the archive leave at their destinations.
+    planned: HashMap<PathBuf, ExistingDestination>,
+    plan: ExtractPlan,
+}
+
+impl<'a> Planner<'a> {
+    pub(crate) fn new(target_dir_path: &str, options: &'a ExtractOptions) -> LibArchiveResult<Self> {
+        let dir_path = Path::new(target_dir_path);
+        let dir_path = if dir_path.exists() {
+            if !dir_path.is_dir() {
+                return Err(LibArchiveError::IsNotDir);
+            }
+            let Ok(v) = dir_path.canonicalize() else {
+                return Err(LibArchiveError::FailedGetMetaDataFromDir);
+            };
+            v
+        } else {
+            let Ok(current_dir) = std::env::current_dir() else {
+                return Err(LibArchiveError::FailedGetMetaDataFromDir);
+            };
+            current_dir.join(dir_path)
+        };
+
+        Ok(Planner {
+            dir_path,
+            options,
+            planned_symlinks: vec!(),
+            planned: HashMap::new(),
+            plan: ExtractPlan::default(),
+        })
+    }
+
+    /// Refuses paths that would be written through a symlink, either one already on disk or
+    /// one created by an earlier entry of the same archive.
+    fn check_symlinks(&self, name: &str) -> LibArchiveResult<()> {
+        if !self.options.is_secure_symlinks() {
+            return Ok(());
+        }
+
+        let mut current = self.dir_path.clone();
+        let components: Vec<_> = Path::new(name).components().collect();
+        for (i, component) in components.iter().enumerate() {
+            current.push(component);
+            if i + 1 == components.len() {
+                break;
+            }
+
+            if self.planned_symlinks.contains(&current) {
+                return Err(LibArchiveError::SymlinkPathRejected);
+            }
+            if let Ok(meta) = std::fs::symlink_metadata(&current) && meta.file_type().is_symlink() {
+                return Err(LibArchiveError::SymlinkPathRejected);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// What is at `name` once the entries planned so far are written.
+    fn existing(&self, name: &str) -> Option<ExistingDestination> {
+        let path = self.dir_path.join(name);
+        self.planned.get(&path).copied().or_else(|| existing_on_disk(&path))
+    }
+
+    /// What writing `metadata` leaves at its destination; the mtime is only kept when times
+    /// are restored.
+    fn left_behind(&self, metadata: &EntryMetadata) -> ExistingDestination {
+        let mtime = if self.options.is_time() {
+            metadata.mtime
+        } else {
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok().map(|v| v.as_secs() as i64)
+        };
+
+        ExistingDestination {
+            is_dir: metadata.entry_type == EntryType::Directory,
+            mtime,
+        }
+    }
+
+    pub(crate) fn add(&mut self, metadata: &EntryMetadata) {
+        let name = metadata.pathname.as_str();
+        let size = match metadata.entry_type {
+            EntryType::File => metadata.size.unwrap_or(0),
+            _ => 0,
+        };
+
+        let checked = check_entry_path(name, self.options)
+            .and_then(|_| match &metadata.hardlink {
+                Some(target) => check_entry_path(target, self.options),
+                None => Ok(()),
+            })
+            .and_then(|_| self.check_symlinks(name))
+            .and_then(|_| decide_destination_with(name, metadata, self.options.get_overwrite_policy(), |candidate| self.existing(candidate)));
+
+        let planned = match checked {
+            Ok((out_name, action)) => {
+                let destination = self.dir_path.join(&out_name);
+                if metadata.entry_type == EntryType::Symlink {
+                    self.planned_symlinks.push(destination.clone());
+                }
+                if action != ExtractAction::Skipped {
+                    self.plan.total_bytes += size;
+                    self.planned.insert(destination.clone(), self.left_behind(metadata));
+                }
+
+                PlannedEntry {
+                    file_name: name.to_owned(),
+                    destination: Some(destination),
+                    would_overwrite: action == ExtractAction::Overwritten,
+                    action: Some(action),
+                    rejected: None,
+                    size,
+                }
+            },
+            Err(e) => {
+                PlannedEntry {
+                    file_name: name.to_owned(),
+                    destination: None,
+                    action: None,
+                    would_overwrite: false,
+                    rejected: Some(e),
+                    size,
+                }
+            }
+        };
+
+        self.plan.entries.push(planned);
+    }
+
+    /// Records an entry left out before any destination is decided, e.g. one whose pathname
+    /// is emptied by `strip_components`.
+    pub(crate) fn skip(&mut self, name: &str) {
+        self.plan.entries.push(PlannedEntry {
+            file_name: name.to_owned(),
+            destination: None,
+            action: Some(ExtractAction::Skipped),
+            would_overwrite: false,
+            rejected: None,
+            size: 0,
+        });
+    }
+
+    /// Records an entry whose header could not be decoded; extraction reports it as failed.
+    pub(crate) fn fail(&mut self, name: &str, error: LibArchiveError) {
+        self.plan.entries.push(PlannedEntry {
+            file_name: name.to_owned(),
+            destination: None,
+            action: None,
+            would_overwrite: false,
+            rejected: Some(error),
+            size: 0,
+        });
+    }
+
+    pub(crate) fn finish(self) -> ExtractPlan {
+        self.plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::OverwritePolicy;
+    use crate::tests::TempDir;
+
+    fn plan_twice(dir: &TempDir, policy: OverwritePolicy) -> ExtractPlan {
+        let options = ExtractOptions::new().overwrite_policy(policy);
+        let mut planner = Planner::new(dir.0.to_str().unwrap(), &options).unwrap();
+        let metadata = EntryMetadata::new("a.txt", EntryType::File);
+        planner.add(&metadata);
+        planner.add(&metadata);
+        planner.finish()
+    }
+
+    #[test]
+    fn repeated_path_is_overwritten() {
+        let dir = TempDir::new("plan-repeat-overwrite");
+        let plan = plan_twice(&dir, OverwritePolicy::Overwrite);
+        assert_eq!(plan.entries[0].action, Some(ExtractAction::Created));
+        assert_eq!(plan.entries[1].action, Some(ExtractAction::Overwritten));
+        assert!(plan.entries[1].would_overwrite);
+    }
+
+    #[test]
+    fn repeated_path_is_renamed_with_keep_both() {
+        let dir = TempDir::new("plan-repeat-keep-both");
+        let plan = plan_twice(&dir, OverwritePolicy::KeepBoth);
+        assert_eq!(plan.entries[1].action, Some(ExtractAction::Renamed("a (1).txt".to_owned())));
+        assert_eq!(plan.entries[1].destination, Some(dir.0.canonicalize().unwrap().join("a (1).txt")));
+    }
+
+    #[test]
+    fn repeated_path_is_rejected_with_fail() {
+        let dir = TempDir::new("plan-repeat-fail");
+        let plan = plan_twice(&dir, OverwritePolicy::Fail);
+        assert!(plan.entries[0].rejected.is_none());
+        assert!(matches!(plan.entries[1].rejected, Some(LibArchiveError::DestinationExists)));
+    }
+
+    #[test]
+    fn repeated_path_is_skipped_with_skip_existing() {
+        let dir = TempDir::new("plan-repeat-skip");
+        let plan = plan_twice(&dir, OverwritePolicy::SkipExisting);
+        assert_eq!(plan.entries[0].action, Some(ExtractAction::Created));
+        assert_eq!(plan.entries[1].action, Some(ExtractAction::Skipped));
+    }
+}
@@ -0,0 +1,93 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveStruct;
+
+use std::sync::Arc;
+
+pub const DEFAULT_PROGRESS_INTERVAL: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Progress<'a> {
+    pub entry_index: usize,
+    pub pathname: &'a str,
+    /// Bytes decompressed so far for the current entry.
+    pub entry_bytes: u64,
+    /// Bytes consumed so far from the compressed input.
+    pub compressed_bytes: u64,
+    /// Size of the compressed input, when it is known.
+    pub compressed_total: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct ProgressHandler(Arc<dyn Fn(&Progress) + Send + Sync>);
+
+impl ProgressHandler {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        ProgressHandler(Arc::new(f))
+    }
+}
+
+impl std::fmt::Debug for ProgressHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("ProgressHandler")
+    }
+}
+
+pub(crate) struct ProgressTracker<'a> {
+    handler: Option<&'a ProgressHandler>,
+    interval: u64,
+    archive: *mut ArchiveStruct,
+    compressed_total: Option<u64>,
+    entry_index: usize,
+    last_reported: u64,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub(crate) fn new(handler: Option<&'a ProgressHandler>, interval: u64, archive: *mut ArchiveStruct, compressed_total: Option<u64>) -> Self {
+        ProgressTracker {
+            handler,
+            interval: interval.max(1),
+            archive,
+            compressed_total,
+            entry_index: 0,
+            last_reported: 0,
+        }
+    }
+
+    pub(crate) fn begin_entry(&mut self, entry_index: usize, pathname: &str) {
+        self.entry_index = entry_index;
+        self.last_reported = 0;
+        self.report(pathname, 0);
+    }
+
+    pub(crate) fn update(&mut self, pathname: &str, entry_bytes: u64) {
+        if entry_bytes - self.last_reported >= self.interval {
+            self.report(pathname, entry_bytes);
+        }
+    }
+
+    pub(crate) fn finish_entry(&mut self, pathname: &str, entry_bytes: u64) {
+        if entry_bytes != self.last_reported {
+            self.report(pathname, entry_bytes);
+        }
+    }
+
+    fn report(&mut self, pathname: &str, entry_bytes: u64) {
+        let Some(handler) = self.handler else {
+            return;
+        };
+
+        self.last_reported = entry_bytes;
+        let compressed_bytes = unsafe { libarchive3_sys::archive_filter_bytes(self.archive, -1) }.max(0) as u64;
+        let progress = Progress {
+            entry_index: self.entry_index,
+            pathname,
+            entry_bytes,
+            compressed_bytes,
+            compressed_total: self.compressed_total,
+        };
+        (handler.0)(&progress);
+    }
+}
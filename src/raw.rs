@@ -0,0 +1,9 @@
+//! Access to the underlying libarchive handles for features the safe wrapper does not cover yet.
+//!
+//! Everything reachable from here hands out raw pointers; using them is `unsafe` and the
+//! pointers must not outlive the handle they were obtained from.
+
+pub use libarchive3_sys_by_madosuki as sys;
+pub use sys::{ArchiveStruct, ArchiveEntryStruct};
+
+pub use crate::handle::{Entry, ReadArchive, WriteArchive, WriteDisk};
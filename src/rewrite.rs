@@ -0,0 +1,303 @@
+use std::sync::Arc;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::entry::EntryMetadata;
+use crate::error::{LibArchiveError, LibArchiveResult};
+use crate::options::ExtractOptions;
+
+/// User callback for `ExtractOptions::rewrite_pathname`.
+type RewriteFn = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+#[derive(Clone)]
+pub struct PathRewriter(Arc<RewriteFn>);
+
+impl PathRewriter {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        PathRewriter(Arc::new(f))
+    }
+}
+
+impl std::fmt::Debug for PathRewriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("PathRewriter")
+    }
+}
+
+/// A `tar --transform` style substitution.
+#[derive(Debug, Clone)]
+pub struct TransformRule {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl TransformRule {
+    /// `replacement` uses the syntax of the regex crate, e.g. `$1`.
+    pub fn new(pattern: &str, replacement: &str, global: bool) -> LibArchiveResult<TransformRule> {
+        let Ok(regex) = Regex::new(pattern) else {
+            return Err(LibArchiveError::InvalidTransform(pattern.to_owned()));
+        };
+
+        Ok(TransformRule {
+            regex,
+            replacement: replacement.to_owned(),
+            global,
+        })
+    }
+
+    /// Parses a sed expression such as `s/^project-[^\/]*/src/` or `s,^(.*)\.txt$,\1.md,`.
+    ///
+    /// Any character may follow `s` as the delimiter. The pattern uses the syntax of the regex
+    /// crate, so groups are written `(...)` rather than `\(...\)`. The replacement understands
+    /// `\1`..`\9` and `&`; the flags `g` (every match) and `i` (ignore case) are supported.
+    pub fn parse(expression: &str) -> LibArchiveResult<TransformRule> {
+        let invalid = || LibArchiveError::InvalidTransform(expression.to_owned());
+
+        let mut chars = expression.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid());
+        }
+        let Some(delimiter) = chars.next() else {
+            return Err(invalid());
+        };
+
+        let mut parts: Vec<String> = vec!(String::new());
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some(next) if next == delimiter && parts.len() == 1 => parts[0].push_str(&regex::escape(&next.to_string())),
+                    Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                    Some(next) => {
+                        let part = parts.last_mut().unwrap();
+                        part.push(c);
+                        part.push(next);
+                    },
+                    None => return Err(invalid()),
+                }
+            } else if c == delimiter {
+                parts.push(String::new());
+            } else {
+                parts.last_mut().unwrap().push(c);
+            }
+        }
+        let [pattern, replacement, flags] = parts.as_slice() else {
+            return Err(invalid());
+        };
+
+        let mut global = false;
+        let mut case_insensitive = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => case_insensitive = true,
+                _ => return Err(invalid()),
+            }
+        }
+
+        let Ok(regex) = RegexBuilder::new(pattern).case_insensitive(case_insensitive).build() else {
+            return Err(invalid());
+        };
+
+        Ok(TransformRule {
+            regex,
+            replacement: sed_replacement(replacement),
+            global,
+        })
+    }
+
+    pub fn apply(&self, path: &str) -> String {
+        if self.global {
+            self.regex.replace_all(path, self.replacement.as_str()).into_owned()
+        } else {
+            self.regex.replace(path, self.replacement.as_str()).into_owned()
+        }
+    }
+}
+
+/// Converts `\1` and `&` of sed into `${1}` and `${0}` of the regex crate.
+fn sed_replacement(replacement: &str) -> String {
+    let mut result = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => result.push_str(&format!("${{{}}}", d)),
+                Some('$') => result.push_str("$$"),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            },
+            '&' => result.push_str("${0}"),
+            '$' => result.push_str("$$"),
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Drops the first `n` components of `path`; `None` when nothing is left.
+///
+/// A trailing `/` of a directory entry is kept.
+pub(crate) fn strip_components(path: &str, n: usize) -> Option<String> {
+    if n == 0 {
+        return Some(path.to_owned());
+    }
+
+    let components: Vec<&str> = path.split('/').filter(|v| !v.is_empty()).collect();
+    if components.len() <= n {
+        return None;
+    }
+
+    let mut stripped = components[n..].join("/");
+    if path.ends_with('/') {
+        stripped.push('/');
+    }
+
+    Some(stripped)
+}
+
+/// Runs `path` through the transform rules and the rewrite callback.
+fn rewrite_path(path: &str, options: &ExtractOptions) -> Option<String> {
+    let mut path = path.to_owned();
+    for rule in options.get_transforms() {
+        path = rule.apply(&path);
+    }
+    if let Some(rewriter) = options.get_path_rewriter() {
+        path = (rewriter.0)(&path)?;
+    }
+
+    if path.is_empty() {
+        return None;
+    }
+
+    Some(path)
+}
+
+/// Applies the pathname options to an entry of the outermost archive before it is reported
+/// or written. Returns `false` when the entry has to be skipped.
+pub(crate) fn rewrite_entry(metadata: &mut EntryMetadata, options: &ExtractOptions) -> bool {
+    let n = options.get_strip_components();
+
+    let Some(pathname) = strip_components(&metadata.pathname, n).and_then(|v| rewrite_path(&v, options)) else {
+        return false;
+    };
+    let hardlink = match &metadata.hardlink {
+        Some(target) => {
+            let stripped = strip_components(target, n);
+            let rewritten = match stripped {
+                Some(v) if options.is_rewrite_link_targets() => rewrite_path(&v, options),
+                v => v,
+            };
+            match rewritten {
+                Some(v) => Some(v),
+                None => return false,
+            }
+        },
+        None => None,
+    };
+    let symlink = match &metadata.symlink {
+        Some(target) if options.is_rewrite_link_targets() => match rewrite_path(target, options) {
+            Some(v) => Some(v),
+            None => return false,
+        },
+        v => v.clone(),
+    };
+
+    metadata.pathname = pathname;
+    metadata.hardlink = hardlink;
+    metadata.symlink = symlink;
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(expression: &str, path: &str) -> String {
+        TransformRule::parse(expression).unwrap().apply(path)
+    }
+
+    #[test]
+    fn parse_any_delimiter() {
+        assert_eq!(apply(r"s/^project-[^\/]*/src/", "project-1.0/a.txt"), "src/a.txt");
+        assert_eq!(apply("s,^old,new,", "old/a.txt"), "new/a.txt");
+        assert_eq!(apply("s#a#b#", "a/a"), "b/a");
+    }
+
+    #[test]
+    fn parse_escaped_delimiter() {
+        assert_eq!(apply(r"s/a\/b/c\/d/", "x/a/b"), "x/c/d");
+        // The delimiter is literal in the pattern even when it is special in a regex.
+        assert_eq!(apply(r"s|a\|b|c|", "a|b"), "c");
+        assert_eq!(apply(r"s.a\.b.c.", "axb/a.b"), "axb/c");
+    }
+
+    #[test]
+    fn parse_escapes_in_pattern() {
+        assert_eq!(apply(r"s/\.txt$/.md/", "a.txt"), "a.md");
+        assert_eq!(apply(r"s/\.txt$/.md/", "atxt"), "atxt");
+    }
+
+    #[test]
+    fn parse_back_references() {
+        assert_eq!(apply(r"s,^(.*)\.txt$,\1.md,", "dir/a.txt"), "dir/a.md");
+        assert_eq!(apply(r"s/(a)(b)/\2\1/", "ab"), "ba");
+        assert_eq!(apply("s/b/[&]/", "abc"), "a[b]c");
+        assert_eq!(apply(r"s/b/\&/", "abc"), "a&c");
+    }
+
+    #[test]
+    fn parse_dollar_is_literal_in_replacement() {
+        assert_eq!(apply("s/a/$1/", "a"), "$1");
+        assert_eq!(apply(r"s/a/\$/", "a"), "$");
+    }
+
+    #[test]
+    fn parse_flags() {
+        assert_eq!(apply("s/a/x/", "aaa"), "xaa");
+        assert_eq!(apply("s/a/x/g", "aaa"), "xxx");
+        assert_eq!(apply("s/a/x/i", "Aaa"), "xaa");
+        assert_eq!(apply("s/a/x/gi", "AaA"), "xxx");
+    }
+
+    #[test]
+    fn parse_rejects_invalid() {
+        for expression in ["", "s", "x/a/b/", "s/a/b", "s/a/b/c/", "s/a/b/q", "s/(/x/", r"s/a/b\"] {
+            assert!(
+                matches!(TransformRule::parse(expression), Err(LibArchiveError::InvalidTransform(_))),
+                "{:?} was accepted",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn strip_nothing() {
+        assert_eq!(strip_components("a/b", 0).as_deref(), Some("a/b"));
+    }
+
+    #[test]
+    fn strip_leading_components() {
+        assert_eq!(strip_components("a/b/c", 1).as_deref(), Some("b/c"));
+        assert_eq!(strip_components("a/b/c", 2).as_deref(), Some("c"));
+        assert_eq!(strip_components("/a/b", 1).as_deref(), Some("b"));
+        assert_eq!(strip_components("a//b", 1).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn strip_keeps_trailing_slash() {
+        assert_eq!(strip_components("a/b/", 1).as_deref(), Some("b/"));
+    }
+
+    #[test]
+    fn strip_everything() {
+        assert_eq!(strip_components("a/b", 2), None);
+        assert_eq!(strip_components("a/", 1), None);
+        assert_eq!(strip_components("a", 3), None);
+    }
+}
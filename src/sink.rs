@@ -0,0 +1,736 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveEntryStruct;
+
+use libc::{c_int, c_void};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{check_entry_path, decide_destination, get_pathname_from_entry, join_to_cstring, lossy_pathname_from_entry, FileInfo};
+use crate::checksum::{Checksums, EntryHasher};
+use crate::entry::{EntryMetadata, EntryType};
+use crate::error::{LibArchiveError, LibArchiveResult};
+use crate::handle::{check_warning, ReadArchive, WriteDisk};
+use crate::links::Link;
+use crate::nested::{is_archive_signature, nested_prefix, SNIFF_SIZE};
+use crate::options::{ExtractAction, ExtractOptions};
+use crate::progress::ProgressTracker;
+use crate::rewrite::rewrite_entry;
+
+/// The header of the entry handed to `ExtractSink::begin_entry`.
+pub struct SinkEntry<'a> {
+    index: usize,
+    metadata: EntryMetadata,
+    entry: *mut ArchiveEntryStruct,
+    _marker: std::marker::PhantomData<&'a ()>,
+}
+
+impl SinkEntry<'_> {
+    /// Position of the entry in the archive, counting every header.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn metadata(&self) -> &EntryMetadata {
+        &self.metadata
+    }
+
+    #[cfg(feature = "raw")]
+    pub fn as_raw(&self) -> *mut ArchiveEntryStruct {
+        self.entry
+    }
+}
+
+/// Destination of extracted entries.
+///
+/// For every entry `begin_entry` is called first; unless it fails or returns
+/// `ExtractAction::Skipped`, the data follows through `write_chunk` and the entry is closed by
+/// `finish_entry`.
+pub trait ExtractSink {
+    fn begin_entry(&mut self, entry: &SinkEntry) -> LibArchiveResult<ExtractAction>;
+    fn write_chunk(&mut self, offset: u64, data: &[u8]) -> LibArchiveResult<()>;
+    fn finish_entry(&mut self) -> LibArchiveResult<()>;
+
+    /// Problems with the current entry that did not stop it, collected into
+    /// `FileInfo::warnings` once the entry is done.
+    fn take_warnings(&mut self) -> Vec<LibArchiveError> {
+        vec!()
+    }
+}
+
+/// Keeps the data of every entry in memory, indexed by entry position.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    values: Vec<Vec<u8>>,
+    links: HashMap<usize, Link>,
+    metadata: HashMap<usize, EntryMetadata>,
+    current: usize,
+    current_size: Option<u64>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_values(self) -> Vec<Vec<u8>> {
+        self.values
+    }
+
+    pub(crate) fn into_parts(self) -> MemoryParts {
+        MemoryParts {
+            values: self.values,
+            links: self.links,
+            metadata: self.metadata,
+        }
+    }
+}
+
+/// What `MemorySink` collected, keyed by entry index.
+pub(crate) struct MemoryParts {
+    pub(crate) values: Vec<Vec<u8>>,
+    pub(crate) links: HashMap<usize, Link>,
+    pub(crate) metadata: HashMap<usize, EntryMetadata>,
+}
+
+impl ExtractSink for MemorySink {
+    fn begin_entry(&mut self, entry: &SinkEntry) -> LibArchiveResult<ExtractAction> {
+        let metadata = entry.metadata();
+        self.metadata.insert(entry.index(), metadata.clone());
+        let link = match (&metadata.hardlink, &metadata.symlink) {
+            (Some(target), _) => Some(Link::Hardlink(target.clone())),
+            (None, Some(target)) if metadata.entry_type == EntryType::Symlink => Some(Link::Symlink(target.clone())),
+            _ => None,
+        };
+        if link.is_none() && metadata.size.unwrap_or(0) < 1 {
+            return Err(LibArchiveError::EntrySizeLessThanOne);
+        }
+
+        self.current = entry.index();
+        self.current_size = metadata.size;
+        if self.values.len() <= self.current {
+            self.values.resize_with(self.current + 1, Vec::new);
+        }
+        if let Some(link) = link {
+            self.links.insert(self.current, link);
+        }
+
+        Ok(ExtractAction::Created)
+    }
+
+    fn write_chunk(&mut self, offset: u64, data: &[u8]) -> LibArchiveResult<()> {
+        write_at(&mut self.values[self.current], offset, data, self.current_size)
+    }
+
+    fn finish_entry(&mut self) -> LibArchiveResult<()> {
+        Ok(())
+    }
+}
+
+/// Writes entries below a directory through `archive_write_disk`.
+pub struct DiskSink {
+    dir_path: PathBuf,
+    options: ExtractOptions,
+    write_disk: WriteDisk,
+    warnings: Vec<LibArchiveError>,
+}
+
+impl DiskSink {
+    /// `dir_path` must be an existing directory.
+    pub fn new(dir_path: &Path, options: &ExtractOptions) -> LibArchiveResult<DiskSink> {
+        if !dir_path.is_dir() {
+            return Err(LibArchiveError::IsNotDir);
+        }
+        let Ok(dir_path) = dir_path.canonicalize() else {
+            return Err(LibArchiveError::FailedGetMetaDataFromDir);
+        };
+
+        let write_disk = WriteDisk::new()?;
+        write_disk.set_options(options.write_disk_flags())?;
+        match options.get_owner_map() {
+            Some(map) => write_disk.set_owner_lookup(map)?,
+            None => write_disk.set_standard_lookup()?,
+        }
+
+        Ok(DiskSink {
+            dir_path,
+            options: options.clone(),
+            write_disk,
+            warnings: vec!(),
+        })
+    }
+
+    pub fn close(self) -> LibArchiveResult<()> {
+        self.write_disk.close()
+    }
+}
+
+impl ExtractSink for DiskSink {
+    fn begin_entry(&mut self, entry: &SinkEntry) -> LibArchiveResult<ExtractAction> {
+        let metadata = entry.metadata();
+        let f_name = metadata.pathname.as_str();
+
+        check_entry_path(f_name, &self.options)?;
+        if let Some(target) = &metadata.hardlink {
+            check_entry_path(target, &self.options)?;
+        }
+
+        let (out_name, action) = decide_destination(&self.dir_path, f_name, metadata, self.options.get_overwrite_policy())?;
+        if action == ExtractAction::Skipped {
+            return Ok(action);
+        }
+
+        let path_with_terminate = join_to_cstring(&self.dir_path, &out_name)?;
+        let hardlink_with_terminate = metadata.hardlink.as_ref()
+            .map(|target| join_to_cstring(&self.dir_path, target))
+            .transpose()?;
+        let symlink_with_terminate = match &metadata.symlink {
+            Some(target) if self.options.is_rewrite_link_targets() => {
+                let Ok(v) = std::ffi::CString::new(target.as_str()) else {
+                    return Err(LibArchiveError::FailedGeneratePath);
+                };
+                Some(v)
+            },
+            _ => None,
+        };
+
+        let write_disk = self.write_disk.as_ptr();
+        unsafe {
+            libarchive3_sys::archive_entry_set_pathname_utf8(entry.entry, path_with_terminate.as_ptr());
+            if let Some(target) = &hardlink_with_terminate {
+                libarchive3_sys::archive_entry_set_hardlink_utf8(entry.entry, target.as_ptr());
+            }
+            if let Some(target) = &symlink_with_terminate {
+                libarchive3_sys::archive_entry_set_symlink_utf8(entry.entry, target.as_ptr());
+            }
+
+            let status_code = libarchive3_sys::archive_write_header(write_disk, entry.entry);
+            match check_warning(write_disk, status_code) {
+                Ok(warning) => self.warnings.extend(warning),
+                Err(e) => {
+                    let _ = libarchive3_sys::archive_write_finish_entry(write_disk);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(action)
+    }
+
+    fn write_chunk(&mut self, offset: u64, data: &[u8]) -> LibArchiveResult<()> {
+        let write_disk = self.write_disk.as_ptr();
+        let write_dta_block_result = unsafe { libarchive3_sys::archive_write_data_block(write_disk, data.as_ptr() as *const c_void, data.len(), offset as i64) };
+        self.warnings.extend(check_warning(write_disk, write_dta_block_result as c_int)?);
+
+        Ok(())
+    }
+
+    fn finish_entry(&mut self) -> LibArchiveResult<()> {
+        let write_disk = self.write_disk.as_ptr();
+        let status_code = unsafe { libarchive3_sys::archive_write_finish_entry(write_disk) };
+        self.warnings.extend(check_warning(write_disk, status_code)?);
+
+        Ok(())
+    }
+
+    fn take_warnings(&mut self) -> Vec<LibArchiveError> {
+        std::mem::take(&mut self.warnings)
+    }
+}
+
+/// State shared by every nesting level of one extraction.
+struct ExtractState<'a> {
+    tracker: ProgressTracker<'a>,
+    total_bytes: u64,
+    entries: usize,
+    result: Vec<FileInfo>,
+}
+
+impl ExtractState<'_> {
+    fn add_bytes(&mut self, options: &ExtractOptions, bytes: u64) -> LibArchiveResult<()> {
+        self.total_bytes += bytes;
+        if options.get_max_total_size().is_some_and(|v| self.total_bytes > v) {
+            return Err(LibArchiveError::ResourceLimitExceeded);
+        }
+
+        Ok(())
+    }
+}
+
+/// Errors that stop the whole extraction rather than a single entry.
+fn abort_error(e: &LibArchiveError) -> Option<LibArchiveError> {
+    match e {
+        LibArchiveError::Cancelled(_) => Some(LibArchiveError::Cancelled(vec!())),
+        LibArchiveError::ResourceLimitExceeded => Some(LibArchiveError::ResourceLimitExceeded),
+        _ => None,
+    }
+}
+
+/// Reads the next block of the current entry, `None` once its data is done.
+pub(crate) fn read_block(read_archive: &ReadArchive) -> LibArchiveResult<Option<(u64, &[u8])>> {
+    let archive = read_archive.as_ptr();
+
+    let mut buf: *mut c_void = std::ptr::null_mut();
+    let mut readed_size = 0_usize;
+    let mut offset = 0_i64;
+    let r = unsafe { libarchive3_sys::archive_read_data_block(archive, &raw mut buf, &mut readed_size, &mut offset) };
+    if r == libarchive3_sys::ARCHIVE_EOF {
+        return Ok(None);
+    }
+    read_archive.check_read_status(r)?;
+    if buf.is_null() {
+        return Ok(None);
+    }
+
+    let data: &[u8] = unsafe { std::slice::from_raw_parts(buf as *const u8, readed_size) };
+    Ok(Some((offset.max(0) as u64, data)))
+}
+
+/// Copies `data` into `buf` at `offset`, filling the hole before it with zeros.
+///
+/// The offset comes from the sparse map of the archive, so a hole may only reach up to
+/// `hole_limit`, usually the size declared by the header; otherwise a crafted entry could
+/// force an allocation of any size.
+pub(crate) fn write_at(buf: &mut Vec<u8>, offset: u64, data: &[u8], hole_limit: Option<u64>) -> LibArchiveResult<()> {
+    let end = offset.saturating_add(data.len() as u64);
+    if offset > buf.len() as u64 && hole_limit.is_none_or(|v| end > v) {
+        return Err(LibArchiveError::ResourceLimitExceeded);
+    }
+
+    let (offset, end) = (offset as usize, end as usize);
+    if buf.len() < end {
+        buf.resize(end, 0);
+    }
+    buf[offset..end].copy_from_slice(data);
+
+    Ok(())
+}
+
+/// Reads the current entry into `buf` until it holds at least `limit` bytes; returns whether
+/// the data is done. The buffered bytes count toward `ExtractOptions::max_total_size`.
+fn buffer_data(read_archive: &ReadArchive, options: &ExtractOptions, state: &mut ExtractState, buf: &mut Vec<u8>, limit: u64) -> LibArchiveResult<bool> {
+    while (buf.len() as u64) < limit {
+        if options.is_cancelled() {
+            return Err(LibArchiveError::Cancelled(vec!()));
+        }
+
+        let Some((offset, data)) = read_block(read_archive)? else {
+            return Ok(true);
+        };
+        let buffered = buf.len() as u64;
+        write_at(buf, offset, data, Some(options.get_max_nested_archive_size()))?;
+        state.add_bytes(options, buf.len() as u64 - buffered)?;
+    }
+
+    Ok(false)
+}
+
+/// Copies the data of the current entry into `sink`. When the size declared by the header of a
+/// file lies past its last block, the entry ends in a hole and is padded up to that size.
+fn copy_data<S: ExtractSink + ?Sized>(read_archive: &ReadArchive, sink: &mut S, options: &ExtractOptions, state: &mut ExtractState, metadata: &EntryMetadata, pending: &[u8], mut hasher: Option<EntryHasher>) -> LibArchiveResult<(u64, Option<Checksums>)> {
+    let pathname = metadata.pathname.as_str();
+    let mut total = 0_u64;
+    // End of the data written so far; holes before a block count toward the limit as well,
+    // since memory sinks fill them with zeros.
+    let mut end = pending.len() as u64;
+
+    // Already counted by `buffer_data`.
+    if !pending.is_empty() {
+        sink.write_chunk(0, pending)?;
+        if let Some(hasher) = &mut hasher {
+            hasher.update(0, pending);
+        }
+
+        total += pending.len() as u64;
+        state.tracker.update(pathname, total);
+    }
+
+    loop {
+        if options.is_cancelled() {
+            return Err(LibArchiveError::Cancelled(vec!()));
+        }
+
+        let Some((offset, data)) = read_block(read_archive)? else {
+            break;
+        };
+        let block_end = offset.saturating_add(data.len() as u64);
+        state.add_bytes(options, block_end.saturating_sub(end))?;
+        end = end.max(block_end);
+        sink.write_chunk(offset, data)?;
+        if let Some(hasher) = &mut hasher {
+            hasher.update(offset, data);
+        }
+
+        total += data.len() as u64;
+        state.tracker.update(pathname, total);
+    }
+
+    let declared_size = metadata.size.filter(|_| metadata.entry_type == EntryType::File);
+    if let Some(size) = declared_size.filter(|v| *v > end) {
+        state.add_bytes(options, size - end)?;
+        sink.write_chunk(size, &[])?;
+        if let Some(hasher) = &mut hasher {
+            hasher.update(size, &[]);
+        }
+    }
+    state.tracker.finish_entry(pathname, total);
+
+    Ok((total, hasher.map(|v| v.finish())))
+}
+
+/// Whether libarchive can read at least one header from `data`.
+fn opens_as_archive(data: &[u8]) -> bool {
+    let Ok(probe) = ReadArchive::new() else {
+        return false;
+    };
+    if probe.support_all_filter_and_format().is_err() || unsafe { probe.open_memory(data) }.is_err() {
+        return false;
+    }
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    let opened = probe.next_header(&mut entry).is_ok();
+    let _ = probe.close();
+
+    opened
+}
+
+/// Buffers the current entry and, when it is an archive, extracts its entries under
+/// `<pathname>!/`.
+///
+/// Returns `false` when the entry has to be kept as a plain file; the data read so far is left
+/// in `pending`.
+fn expand_nested<S: ExtractSink + ?Sized>(read_archive: &ReadArchive, sink: &mut S, options: &ExtractOptions, state: &mut ExtractState, pathname: &str, depth: usize, pending: &mut Vec<u8>) -> LibArchiveResult<bool> {
+    buffer_data(read_archive, options, state, pending, SNIFF_SIZE as u64)?;
+    if !is_archive_signature(pending) {
+        return Ok(false);
+    }
+    if !buffer_data(read_archive, options, state, pending, options.get_max_nested_archive_size().saturating_add(1))? {
+        return Ok(false);
+    }
+    if !opens_as_archive(pending) {
+        return Ok(false);
+    }
+
+    let nested = ReadArchive::new()?;
+    nested.support_all_filter_and_format()?;
+    unsafe { nested.open_memory(pending) }?;
+
+    let checksums = EntryHasher::new(options.get_checksums()).map(|mut hasher| {
+        hasher.update(0, pending);
+        hasher.finish()
+    });
+
+    let container_index = state.result.len();
+    let file_info = FileInfo {
+        file_name: pathname.to_owned(),
+        size: pending.len(),
+        is_success: true,
+        error: None,
+        action: Some(ExtractAction::Expanded),
+        checksums,
+        warnings: read_archive.take_warnings(),
+    };
+    state.result.push(file_info);
+    state.tracker.finish_entry(pathname, pending.len() as u64);
+
+    let extracted = extract_level(&nested, sink, options, state, &nested_prefix(pathname), depth + 1);
+    let _ = nested.close();
+    if let Err(e) = extracted {
+        if let Some(abort) = abort_error(&e) {
+            return Err(abort);
+        }
+
+        let file_info = &mut state.result[container_index];
+        file_info.is_success = false;
+        file_info.error = Some(e);
+    }
+
+    Ok(true)
+}
+
+/// Warnings about the current entry from both the reader and the sink.
+fn entry_warnings<S: ExtractSink + ?Sized>(read_archive: &ReadArchive, sink: &mut S) -> Vec<LibArchiveError> {
+    let mut warnings = read_archive.take_warnings();
+    warnings.extend(sink.take_warnings());
+
+    warnings
+}
+
+fn extract_level<S: ExtractSink + ?Sized>(read_archive: &ReadArchive, sink: &mut S, options: &ExtractOptions, state: &mut ExtractState, prefix: &str, depth: usize) -> LibArchiveResult<()> {
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    loop {
+        // Checked before the header is read, so nothing after a cancelled entry is touched.
+        if options.is_cancelled() {
+            return Err(LibArchiveError::Cancelled(vec!()));
+        }
+        let has_entry = match read_archive.next_header(&mut entry) {
+            Ok(v) => v,
+            Err(e) => {
+                // Nothing past a broken header can be read; it is reported under the name of
+                // the archive level, empty at the top, after the entries extracted so far.
+                state.result.push(FileInfo {
+                    file_name: prefix.to_owned(),
+                    size: 0,
+                    is_success: false,
+                    error: Some(e),
+                    action: None,
+                    checksums: None,
+                    warnings: read_archive.take_warnings(),
+                });
+
+                break;
+            }
+        };
+        if !has_entry {
+            break;
+        }
+
+        state.entries += 1;
+        if options.get_max_entries().is_some_and(|v| state.entries > v) {
+            return Err(LibArchiveError::ResourceLimitExceeded);
+        }
+
+        let entry_index = state.result.len();
+        let mut metadata = match get_pathname_from_entry(entry).and_then(|_| EntryMetadata::from_entry(entry)) {
+            Ok(v) => v,
+            Err(e) => {
+                let file_info = FileInfo {
+                    file_name: format!("{}{}", prefix, lossy_pathname_from_entry(entry)),
+                    size: 0,
+                    is_success: false,
+                    error: Some(e),
+                    action: None,
+                    checksums: None,
+                    warnings: read_archive.take_warnings(),
+                };
+                state.result.push(file_info);
+
+                continue;
+            }
+        };
+        if depth == 0 && !rewrite_entry(&mut metadata, options) {
+            let file_info = FileInfo {
+                file_name: metadata.pathname,
+                size: 0,
+                is_success: true,
+                error: None,
+                action: Some(ExtractAction::Skipped),
+                checksums: None,
+                warnings: read_archive.take_warnings(),
+            };
+            state.result.push(file_info);
+
+            continue;
+        }
+        if !prefix.is_empty() {
+            metadata.pathname = format!("{}{}", prefix, metadata.pathname);
+            metadata.hardlink = metadata.hardlink.map(|v| format!("{}{}", prefix, v));
+        }
+        let f_name = metadata.pathname.clone();
+
+        let mut pending: Vec<u8> = vec!();
+        let sniffed = depth < options.get_max_depth() && metadata.entry_type == EntryType::File;
+        if sniffed {
+            state.tracker.begin_entry(entry_index, &f_name);
+            match expand_nested(read_archive, sink, options, state, &f_name, depth, &mut pending) {
+                Ok(true) => continue,
+                Ok(false) => {},
+                Err(e) => {
+                    if let Some(abort) = abort_error(&e) {
+                        return Err(abort);
+                    }
+
+                    let file_info = FileInfo {
+                        file_name: f_name,
+                        size: 0,
+                        is_success: false,
+                        error: Some(e),
+                        action: None,
+                        checksums: None,
+                        warnings: read_archive.take_warnings(),
+                    };
+                    state.result.push(file_info);
+
+                    continue;
+                }
+            }
+        }
+
+        let hasher = match metadata.entry_type {
+            EntryType::File => EntryHasher::new(options.get_checksums()),
+            _ => None,
+        };
+        let sink_entry = SinkEntry {
+            index: entry_index,
+            metadata,
+            entry,
+            _marker: std::marker::PhantomData,
+        };
+        let action = match sink.begin_entry(&sink_entry) {
+            Ok(v) => v,
+            Err(e) => {
+                let file_info = FileInfo {
+                    file_name: f_name,
+                    size: 0,
+                    is_success: false,
+                    error: Some(e),
+                    action: None,
+                    checksums: None,
+                    warnings: entry_warnings(read_archive, sink),
+                };
+                state.result.push(file_info);
+
+                continue;
+            }
+        };
+        if action == ExtractAction::Skipped {
+            let file_info = FileInfo {
+                file_name: f_name,
+                size: 0,
+                is_success: true,
+                error: None,
+                action: Some(action),
+                checksums: None,
+                warnings: entry_warnings(read_archive, sink),
+            };
+            state.result.push(file_info);
+
+            continue;
+        }
+
+        if !sniffed {
+            state.tracker.begin_entry(entry_index, &f_name);
+        }
+        let copied = copy_data(read_archive, sink, options, state, &sink_entry.metadata, &pending, hasher);
+        let finished = sink.finish_entry();
+
+        let (size, checksums, error) = match (copied, finished) {
+            (Ok((size, checksums)), Ok(_)) => (size, checksums, None),
+            (Ok((size, _)), Err(e)) => (size, None, Some(e)),
+            (Err(e), _) => (0, None, Some(e)),
+        };
+        let abort = error.as_ref().and_then(abort_error);
+
+        let file_info = FileInfo {
+            file_name: f_name,
+            size: size as usize,
+            is_success: error.is_none(),
+            error,
+            action: Some(action),
+            checksums,
+            warnings: entry_warnings(read_archive, sink),
+        };
+        state.result.push(file_info);
+
+        if let Some(e) = abort {
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Feeds every entry of `read_archive` into `sink`, producing one `FileInfo` per header.
+///
+/// With `ExtractOptions::recursive` an expanded nested archive gets its own `FileInfo`
+/// followed by those of its entries.
+pub(crate) fn extract_entries<S: ExtractSink + ?Sized>(read_archive: &ReadArchive, sink: &mut S, options: &ExtractOptions, archive_size: Option<u64>) -> LibArchiveResult<Vec<FileInfo>> {
+    let (progress, progress_interval) = options.get_progress();
+    let mut state = ExtractState {
+        tracker: ProgressTracker::new(progress, progress_interval, read_archive.as_ptr(), archive_size),
+        total_bytes: 0,
+        entries: 0,
+        result: vec!(),
+    };
+
+    match extract_level(read_archive, sink, options, &mut state, "", 0) {
+        Ok(_) => Ok(state.result),
+        Err(LibArchiveError::Cancelled(_)) => Err(LibArchiveError::Cancelled(state.result)),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Archive, ArchiveExt, ExtractOptions};
+    use crate::checksum::{ChecksumAlgorithm, EntryHasher};
+    use crate::entry::{EntryMetadata, EntryType};
+    use crate::tests::TempDir;
+    use crate::writer::{ArchiveFilter, ArchiveFormat, ArchiveWriter};
+
+    fn octal(field: &mut [u8], value: u64) {
+        let text = format!("{:0width$o}", value, width = field.len() - 1);
+        field[..text.len()].copy_from_slice(text.as_bytes());
+    }
+
+    /// A GNU tar holding `name` as an old-style sparse file of `real_size` bytes whose only
+    /// data is `data` at offset 0, so that it ends in a hole.
+    fn gnu_sparse_tar(name: &str, data: &[u8], real_size: u64) -> Vec<u8> {
+        let mut header = [0_u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], 0);
+        header[156] = b'S';
+        header[257..265].copy_from_slice(b"ustar  \0");
+        octal(&mut header[386..398], 0);
+        octal(&mut header[398..410], data.len() as u64);
+        octal(&mut header[483..495], real_size);
+
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u64 = header.iter().map(|v| *v as u64).sum();
+        let text = format!("{:06o}\0 ", sum);
+        header[148..156].copy_from_slice(text.as_bytes());
+
+        let mut result = header.to_vec();
+        result.extend_from_slice(data);
+        result.resize(result.len().div_ceil(512) * 512 + 1024, 0);
+        result
+    }
+
+    #[test]
+    fn trailing_hole_is_padded_and_hashed() {
+        let dir = TempDir::new("sink-trailing-hole");
+        let path = dir.0.join("sparse.tar");
+        let path_str = path.to_str().unwrap();
+        std::fs::write(&path, gnu_sparse_tar("sparse", &[7_u8; 512], 4096)).unwrap();
+
+        let options = ExtractOptions::new().checksum(ChecksumAlgorithm::Sha256);
+        let result = Archive::new().unwrap().extract_to_memory(path_str, &options).unwrap();
+        assert_eq!(result.len(), 1);
+        assert!(result[0].file_info.is_success);
+
+        let mut dense = vec![7_u8; 512];
+        dense.resize(4096, 0);
+        assert_eq!(&result[0].value[..], &dense[..]);
+
+        let mut hasher = EntryHasher::new(&[ChecksumAlgorithm::Sha256]).unwrap();
+        hasher.update(0, &dense);
+        assert_eq!(result[0].file_info.checksums.as_ref().unwrap().sha256, hasher.finish().sha256);
+    }
+
+    #[test]
+    fn broken_header_keeps_entries_read_before() {
+        let dir = TempDir::new("sink-broken-header");
+        let path = dir.0.join("a.tar");
+        let path_str = path.to_str().unwrap();
+
+        let mut writer = ArchiveWriter::create(path_str, ArchiveFormat::Ustar, ArchiveFilter::None).unwrap();
+        writer.write_entry(&EntryMetadata::new("a.txt", EntryType::File), b"a").unwrap();
+        writer.write_entry(&EntryMetadata::new("b.txt", EntryType::File), b"b").unwrap();
+        writer.close().unwrap();
+
+        // Cut into the second header, after the first header and its data block.
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..1024 + 100]).unwrap();
+
+        let result = Archive::new().unwrap().extract_to_memory(path_str, &ExtractOptions::new()).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].file_info.file_name, "a.txt");
+        assert!(result[0].file_info.is_success);
+        assert_eq!(&result[0].value[..], b"a");
+        assert!(!result[1].file_info.is_success);
+        assert!(result[1].file_info.error.is_some());
+    }
+}
@@ -0,0 +1,57 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveEntryStruct;
+
+use crate::entry::{EntryMetadata, EntryType};
+use crate::error::LibArchiveResult;
+use crate::handle::ReadArchive;
+
+/// Counts and sizes of an archive, read from its headers only.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchiveSummary {
+    /// Format as named by libarchive, e.g. `POSIX pax interchange format`.
+    pub format: Option<String>,
+    /// Compression filters, e.g. `["gzip"]`.
+    pub filters: Vec<String>,
+    pub entries: usize,
+    pub files: usize,
+    pub directories: usize,
+    pub symlinks: usize,
+    pub hardlinks: usize,
+    /// Devices, fifos, sockets and unknown entries.
+    pub others: usize,
+    pub encrypted: usize,
+    /// Sum of the sizes stored in the headers.
+    pub total_size: u64,
+    /// Size of the archive file itself.
+    pub archive_size: u64,
+}
+
+pub(crate) fn summarize(read_archive: &ReadArchive, archive_size: u64) -> LibArchiveResult<ArchiveSummary> {
+    let mut summary = ArchiveSummary {
+        archive_size,
+        ..ArchiveSummary::default()
+    };
+
+    let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+    while read_archive.next_header(&mut entry)? {
+        let metadata = EntryMetadata::from_entry(entry)?;
+        summary.entries += 1;
+        match metadata.entry_type {
+            EntryType::File => summary.files += 1,
+            EntryType::Directory => summary.directories += 1,
+            EntryType::Symlink => summary.symlinks += 1,
+            EntryType::Hardlink => summary.hardlinks += 1,
+            _ => summary.others += 1,
+        }
+        if metadata.is_encrypted {
+            summary.encrypted += 1;
+        }
+        summary.total_size += metadata.size.unwrap_or(0);
+    }
+
+    summary.format = read_archive.format_name();
+    summary.filters = read_archive.filter_names();
+
+    Ok(summary)
+}
@@ -0,0 +1,191 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::Path;
+
+use crate::checksum::{ChecksumAlgorithm, EntryHasher};
+use crate::diff::{metadata_changes, ChangedEntry, IndexedEntry};
+use crate::entry::{EntryMetadata, EntryType};
+use crate::error::LibArchiveResult;
+use crate::options::ExtractOptions;
+use crate::rewrite::rewrite_entry;
+use crate::vfs::normalize;
+use crate::writer::metadata_from_disk;
+
+/// An extracted directory or an archive compared with what it is expected to hold, either
+/// the archive it was extracted from or an mtree manifest.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VerifyReport {
+    /// Expected entries that were not found.
+    pub missing: Vec<String>,
+    /// Entries found that nothing expected accounts for.
+    pub extra: Vec<String>,
+    /// Entries whose data or metadata differ; the expected side is `old`.
+    pub mismatched: Vec<ChangedEntry>,
+    /// Entries of the archive whose header could not be decoded, by lossily decoded pathname;
+    /// they are left out of the comparison.
+    pub unreadable: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.mismatched.is_empty() && self.unreadable.is_empty()
+    }
+}
+
+/// Applies the pathname options of `options` to `index`, keyed by the pathname each entry
+/// is extracted to. Entries the options skip are left out.
+pub(crate) fn expected_entries(index: BTreeMap<String, IndexedEntry>, options: &ExtractOptions) -> BTreeMap<String, IndexedEntry> {
+    let mut expected: BTreeMap<String, IndexedEntry> = BTreeMap::new();
+    for (_, mut v) in index {
+        if !rewrite_entry(&mut v.metadata, options) {
+            continue;
+        }
+        if let Some(pathname) = normalize(&v.metadata.pathname).filter(|v| !v.is_empty()) {
+            expected.insert(pathname, v);
+        }
+    }
+
+    expected
+}
+
+/// Every path below `dir`, without following symlinks.
+fn walk(dir: &Path, prefix: &str, out: &mut BTreeMap<String, std::fs::Metadata>) -> LibArchiveResult<()> {
+    for child in std::fs::read_dir(dir)? {
+        let child = child?;
+        let name = format!("{}{}", prefix, child.file_name().to_string_lossy());
+        let meta = std::fs::symlink_metadata(child.path())?;
+        if meta.is_dir() {
+            walk(&child.path(), &format!("{}/", name), out)?;
+        }
+        out.insert(name, meta);
+    }
+
+    Ok(())
+}
+
+/// Every path below `dir_path` with its metadata, hashing the data of regular files.
+pub(crate) fn index_dir(dir_path: &Path) -> LibArchiveResult<BTreeMap<String, IndexedEntry>> {
+    let mut on_disk: BTreeMap<String, std::fs::Metadata> = BTreeMap::new();
+    walk(dir_path, "", &mut on_disk)?;
+
+    let mut index: BTreeMap<String, IndexedEntry> = BTreeMap::new();
+    for (pathname, meta) in on_disk {
+        let path = dir_path.join(&pathname);
+        let mut metadata = metadata_from_disk(&pathname, &meta);
+        let sha256 = match metadata.entry_type {
+            EntryType::File => {
+                metadata.size = Some(meta.len());
+                hash_file(&path)?
+            },
+            EntryType::Symlink => {
+                metadata.symlink = Some(std::fs::read_link(&path)?.to_string_lossy().into_owned());
+                None
+            },
+            _ => None,
+        };
+        index.insert(pathname, IndexedEntry { metadata, sha256 });
+    }
+
+    Ok(index)
+}
+
+/// Paths found in `found` but not in `expected`, leaving out the directories created for
+/// expected entries that have no entry of their own.
+pub(crate) fn extra_paths<'a>(expected: &BTreeMap<String, IndexedEntry>, found: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let parents: BTreeSet<&str> = expected.keys()
+        .flat_map(|v| v.match_indices('/').map(move |(i, _)| &v[..i]))
+        .collect();
+
+    found.filter(|v| !expected.contains_key(*v) && !parents.contains(v.as_str()))
+        .cloned()
+        .collect()
+}
+
+fn hash_file(path: &Path) -> LibArchiveResult<Option<[u8; 32]>> {
+    let mut hasher = EntryHasher::new(&[ChecksumAlgorithm::Sha256]).unwrap_or_default();
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0_u8; 64 * 1024];
+    let mut position = 0_u64;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(position, &buf[..n]);
+        position += n as u64;
+    }
+
+    Ok(hasher.finish().sha256)
+}
+
+/// What extraction with `options` leaves on disk for `metadata`: hardlinks are files, and
+/// the metadata that is not restored is taken from `disk` so that it compares equal.
+fn extracted_metadata(metadata: &EntryMetadata, disk: &EntryMetadata, options: &ExtractOptions) -> EntryMetadata {
+    let mut expected = metadata.clone();
+    if expected.entry_type == EntryType::Hardlink {
+        expected.entry_type = EntryType::File;
+        expected.hardlink = None;
+    }
+
+    // Symlink modes are not restored on most systems.
+    if !options.is_perm() || expected.entry_type == EntryType::Symlink {
+        expected.mode = disk.mode;
+    }
+    if options.is_owner() {
+        let map = options.get_owner_map().cloned().unwrap_or_default();
+        expected.uid = map.resolve_uid(metadata.uname.as_deref(), metadata.uid);
+        expected.gid = map.resolve_gid(metadata.gname.as_deref(), metadata.gid);
+    } else {
+        expected.uid = disk.uid;
+        expected.gid = disk.gid;
+    }
+    // Compared by the ids they resolved to.
+    expected.uname = None;
+    expected.gname = None;
+    if !options.is_time() {
+        expected.mtime = disk.mtime;
+    }
+
+    expected
+}
+
+/// Compares the entries `expected` with the tree below `dir_path`.
+pub(crate) fn verify_entries(expected: &BTreeMap<String, IndexedEntry>, dir_path: &Path, options: &ExtractOptions) -> LibArchiveResult<VerifyReport> {
+    let mut report = VerifyReport::default();
+
+    let mut on_disk: BTreeMap<String, std::fs::Metadata> = BTreeMap::new();
+    walk(dir_path, "", &mut on_disk)?;
+
+    for (pathname, entry) in expected {
+        let Some(meta) = on_disk.get(pathname) else {
+            report.missing.push(pathname.clone());
+            continue;
+        };
+
+        let path = dir_path.join(pathname);
+        let mut disk = metadata_from_disk(pathname, meta);
+        disk.uname = None;
+        disk.gname = None;
+        if disk.entry_type == EntryType::Symlink {
+            disk.symlink = Some(std::fs::read_link(&path)?.to_string_lossy().into_owned());
+        }
+
+        let metadata = metadata_changes(&extracted_metadata(&entry.metadata, &disk, options), &disk);
+        let content_changed = match entry.sha256 {
+            Some(sha256) if disk.entry_type == EntryType::File => hash_file(&path)? != Some(sha256),
+            _ => false,
+        };
+        if content_changed || !metadata.is_empty() {
+            report.mismatched.push(ChangedEntry {
+                pathname: pathname.clone(),
+                content_changed,
+                metadata,
+            });
+        }
+    }
+
+    report.extra = extra_paths(expected, on_disk.keys());
+
+    Ok(report)
+}
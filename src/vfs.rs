@@ -0,0 +1,320 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::Read;
+use std::sync::Arc;
+
+use crate::FileInfo;
+use crate::entry::{EntryMetadata, EntryType};
+use crate::error::{LibArchiveError, LibArchiveResult};
+use crate::options::ExtractAction;
+use crate::sink::{write_at, ExtractSink, SinkEntry};
+
+pub(crate) const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// Strips `.`, leading `/` and trailing `/` so that `./a//b/` and `a/b` name the same node.
+pub(crate) fn normalize(path: &str) -> Option<String> {
+    let mut components: Vec<&str> = vec!();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {},
+            ".." => {
+                components.pop()?;
+            },
+            v => components.push(v),
+        }
+    }
+
+    Some(components.join("/"))
+}
+
+pub(crate) fn parent_of(path: &str) -> Option<&str> {
+    if path.is_empty() {
+        return None;
+    }
+
+    match path.rfind('/') {
+        Some(i) => Some(&path[..i]),
+        None => Some(""),
+    }
+}
+
+fn directory_metadata(path: &str) -> EntryMetadata {
+    EntryMetadata::new(path, EntryType::Directory)
+}
+
+#[derive(Debug)]
+struct Node {
+    metadata: EntryMetadata,
+    synthesized: bool,
+    data: Arc<[u8]>,
+    children: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub path: String,
+    pub entry_type: EntryType,
+}
+
+/// A reader over the data of one file in an `ArchiveFs`.
+#[derive(Debug)]
+pub struct ArchiveFile {
+    data: Arc<[u8]>,
+    position: usize,
+}
+
+impl ArchiveFile {
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Read for ArchiveFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = &self.data[self.position.min(self.data.len())..];
+        let n = remaining.len().min(buf.len());
+        buf[..n].copy_from_slice(&remaining[..n]);
+        self.position += n;
+
+        Ok(n)
+    }
+}
+
+/// Read-only view of an archive as a directory tree.
+///
+/// Paths are `/`-separated and relative to the archive root; directories missing from the
+/// archive are synthesized from the paths of their descendants.
+#[derive(Debug)]
+pub struct ArchiveFs {
+    nodes: HashMap<String, Node>,
+    failed: Vec<FileInfo>,
+}
+
+impl ArchiveFs {
+    pub(crate) fn from_entries(entries: Vec<(EntryMetadata, Vec<u8>)>, failed: Vec<FileInfo>) -> ArchiveFs {
+        let mut fs = ArchiveFs { nodes: HashMap::new(), failed };
+        fs.nodes.insert("".to_owned(), Node {
+            metadata: directory_metadata(""),
+            synthesized: true,
+            data: Arc::from(Vec::new()),
+            children: BTreeSet::new(),
+        });
+
+        let mut hardlinks: Vec<(String, String)> = vec!();
+        for (metadata, data) in entries {
+            let Some(path) = normalize(&metadata.pathname) else {
+                continue;
+            };
+            if path.is_empty() {
+                continue;
+            }
+
+            if let Some(target) = metadata.hardlink.as_deref().and_then(normalize) {
+                hardlinks.push((path.clone(), target));
+            }
+
+            fs.insert_parents(&path);
+            let children = fs.nodes.remove(&path).map(|v| v.children).unwrap_or_default();
+            fs.nodes.insert(path, Node {
+                metadata,
+                synthesized: false,
+                data: Arc::from(data),
+                children,
+            });
+        }
+
+        for (path, target) in hardlinks {
+            let Some(data) = fs.nodes.get(&target).map(|v| v.data.clone()) else {
+                continue;
+            };
+            if let Some(node) = fs.nodes.get_mut(&path) {
+                node.data = data;
+            }
+        }
+
+        fs
+    }
+
+    fn insert_parents(&mut self, path: &str) {
+        let mut child = path.to_owned();
+        while let Some(parent) = parent_of(&child).map(|v| v.to_owned()) {
+            let node = self.nodes.entry(parent.clone()).or_insert_with(|| Node {
+                metadata: directory_metadata(&parent),
+                synthesized: true,
+                data: Arc::from(Vec::new()),
+                children: BTreeSet::new(),
+            });
+            let name = child.rsplit('/').next().unwrap_or(&child).to_owned();
+            if !node.children.insert(name) {
+                return;
+            }
+            child = parent;
+        }
+    }
+
+    /// Resolves symlinks within the archive one path component at a time, including the last
+    /// component only when `follow_last` is set. Returns the normalized path of the node.
+    fn resolve_path(&self, path: &str, follow_last: bool) -> LibArchiveResult<String> {
+        let Some(path) = normalize(path) else {
+            return Err(LibArchiveError::IsNotExists);
+        };
+
+        let mut pending: VecDeque<String> = path.split('/').map(|v| v.to_owned()).collect();
+        let mut resolved = String::new();
+        let mut hops = 0;
+        while let Some(component) = pending.pop_front() {
+            match component.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    let Some(parent) = parent_of(&resolved) else {
+                        return Err(LibArchiveError::IsNotExists);
+                    };
+                    resolved = parent.to_owned();
+                    continue;
+                },
+                _ => {},
+            }
+
+            let candidate = if resolved.is_empty() { component } else { format!("{}/{}", resolved, component) };
+            let Some(node) = self.nodes.get(&candidate) else {
+                return Err(LibArchiveError::IsNotExists);
+            };
+            let (EntryType::Symlink, Some(target)) = (node.metadata.entry_type, &node.metadata.symlink) else {
+                resolved = candidate;
+                continue;
+            };
+            if pending.is_empty() && !follow_last {
+                resolved = candidate;
+                continue;
+            }
+
+            hops += 1;
+            if hops > MAX_SYMLINK_DEPTH {
+                return Err(LibArchiveError::SymlinkLoop);
+            }
+            if target.starts_with('/') {
+                resolved.clear();
+            }
+            for component in target.split('/').rev() {
+                pending.push_front(component.to_owned());
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Follows symlinks within the archive until a non-symlink node is reached.
+    fn resolve(&self, path: &str) -> LibArchiveResult<&Node> {
+        let path = self.resolve_path(path, true)?;
+        self.nodes.get(&path).ok_or(LibArchiveError::IsNotExists)
+    }
+
+    /// The node at `path` itself, following symlinks only in its parent directories.
+    fn lookup(&self, path: &str) -> Option<&Node> {
+        self.resolve_path(path, false).ok().and_then(|v| self.nodes.get(&v))
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.lookup(path).is_some()
+    }
+
+    /// Metadata of the node itself; symlinks are not followed.
+    pub fn metadata(&self, path: &str) -> LibArchiveResult<&EntryMetadata> {
+        match self.lookup(path) {
+            Some(node) => Ok(&node.metadata),
+            None => Err(LibArchiveError::IsNotExists),
+        }
+    }
+
+    /// Whether the directory at `path` was synthesized rather than stored in the archive.
+    pub fn is_synthesized(&self, path: &str) -> bool {
+        self.lookup(path).is_some_and(|v| v.synthesized)
+    }
+
+    /// Entries left out because they could not be read, with their errors.
+    pub fn failed_entries(&self) -> &[FileInfo] {
+        &self.failed
+    }
+
+    pub fn read_dir(&self, path: &str) -> LibArchiveResult<Vec<DirEntry>> {
+        let node = self.resolve(path)?;
+        if node.metadata.entry_type != EntryType::Directory {
+            return Err(LibArchiveError::IsNotDir);
+        }
+
+        let base = normalize(&node.metadata.pathname).unwrap_or_default();
+        let result = node.children.iter()
+            .map(|name| {
+                let path = if base.is_empty() { name.clone() } else { format!("{}/{}", base, name) };
+                let entry_type = self.nodes.get(&path).map(|v| v.metadata.entry_type).unwrap_or(EntryType::Unknown);
+                DirEntry {
+                    name: name.clone(),
+                    path,
+                    entry_type,
+                }
+            })
+            .collect();
+
+        Ok(result)
+    }
+
+    pub fn open(&self, path: &str) -> LibArchiveResult<ArchiveFile> {
+        let node = self.resolve(path)?;
+        match node.metadata.entry_type {
+            EntryType::File | EntryType::Hardlink => {
+                Ok(ArchiveFile {
+                    data: node.data.clone(),
+                    position: 0,
+                })
+            },
+            _ => Err(LibArchiveError::IsNotFile),
+        }
+    }
+}
+
+/// Collects metadata and data of every entry for `ArchiveFs`, by entry position.
+#[derive(Debug, Default)]
+pub(crate) struct FsSink {
+    entries: Vec<(usize, EntryMetadata, Vec<u8>)>,
+}
+
+impl FsSink {
+    /// Leaves out the entries whose `FileInfo` in `file_infos` has an error.
+    pub(crate) fn into_fs(self, file_infos: Vec<FileInfo>) -> ArchiveFs {
+        let failed_indexes: HashSet<usize> = file_infos.iter()
+            .enumerate()
+            .filter(|(_, v)| v.error.is_some())
+            .map(|(i, _)| i)
+            .collect();
+        let entries = self.entries.into_iter()
+            .filter(|(i, _, _)| !failed_indexes.contains(i))
+            .map(|(_, metadata, data)| (metadata, data))
+            .collect();
+        let failed = file_infos.into_iter().filter(|v| v.error.is_some()).collect();
+
+        ArchiveFs::from_entries(entries, failed)
+    }
+}
+
+impl ExtractSink for FsSink {
+    fn begin_entry(&mut self, entry: &SinkEntry) -> LibArchiveResult<ExtractAction> {
+        self.entries.push((entry.index(), entry.metadata().clone(), vec!()));
+        Ok(ExtractAction::Created)
+    }
+
+    fn write_chunk(&mut self, offset: u64, data: &[u8]) -> LibArchiveResult<()> {
+        let Some((_, metadata, value)) = self.entries.last_mut() else {
+            return Err(LibArchiveError::Null);
+        };
+
+        write_at(value, offset, data, metadata.size)
+    }
+
+    fn finish_entry(&mut self) -> LibArchiveResult<()> {
+        Ok(())
+    }
+}
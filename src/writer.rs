@@ -0,0 +1,699 @@
+use libarchive3_sys_by_madosuki as libarchive3_sys;
+use libarchive3_sys::ArchiveEntryStruct;
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::convert::copy_entry;
+use crate::lossy_pathname_from_entry;
+use crate::entry::{EntryMetadata, EntryType, FailedEntry};
+use crate::error::{LibArchiveError, LibArchiveResult};
+use crate::handle::{Entry, ReadArchive, WriteArchive};
+#[cfg(unix)]
+use crate::owner;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ArchiveFormat {
+    /// POSIX pax tar; keeps xattrs, ACLs and long names.
+    Pax,
+    Ustar,
+    GnuTar,
+    Zip,
+    SevenZip,
+    Cpio,
+    Iso9660,
+    Xar,
+    Mtree,
+}
+
+impl ArchiveFormat {
+    /// The name understood by `archive_write_set_format_by_name`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Pax => "pax",
+            ArchiveFormat::Ustar => "ustar",
+            ArchiveFormat::GnuTar => "gnutar",
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::SevenZip => "7zip",
+            ArchiveFormat::Cpio => "cpio",
+            ArchiveFormat::Iso9660 => "iso9660",
+            ArchiveFormat::Xar => "xar",
+            ArchiveFormat::Mtree => "mtree",
+        }
+    }
+}
+
+impl std::str::FromStr for ArchiveFormat {
+    type Err = LibArchiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pax" | "tar" => Ok(ArchiveFormat::Pax),
+            "ustar" => Ok(ArchiveFormat::Ustar),
+            "gnutar" => Ok(ArchiveFormat::GnuTar),
+            "zip" => Ok(ArchiveFormat::Zip),
+            "7zip" | "7z" => Ok(ArchiveFormat::SevenZip),
+            "cpio" => Ok(ArchiveFormat::Cpio),
+            "iso9660" | "iso" => Ok(ArchiveFormat::Iso9660),
+            "xar" => Ok(ArchiveFormat::Xar),
+            "mtree" => Ok(ArchiveFormat::Mtree),
+            _ => Err(LibArchiveError::UnsupportedFormat(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ArchiveFilter {
+    #[default]
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Lzma,
+    Zstd,
+    Lz4,
+    Compress,
+}
+
+impl ArchiveFilter {
+    /// The name understood by `archive_write_add_filter_by_name`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArchiveFilter::None => "none",
+            ArchiveFilter::Gzip => "gzip",
+            ArchiveFilter::Bzip2 => "bzip2",
+            ArchiveFilter::Xz => "xz",
+            ArchiveFilter::Lzma => "lzma",
+            ArchiveFilter::Zstd => "zstd",
+            ArchiveFilter::Lz4 => "lz4",
+            ArchiveFilter::Compress => "compress",
+        }
+    }
+}
+
+impl std::str::FromStr for ArchiveFilter {
+    type Err = LibArchiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ArchiveFilter::None),
+            "gzip" | "gz" => Ok(ArchiveFilter::Gzip),
+            "bzip2" | "bz2" => Ok(ArchiveFilter::Bzip2),
+            "xz" => Ok(ArchiveFilter::Xz),
+            "lzma" => Ok(ArchiveFilter::Lzma),
+            "zstd" | "zst" => Ok(ArchiveFilter::Zstd),
+            "lz4" => Ok(ArchiveFilter::Lz4),
+            "compress" | "Z" => Ok(ArchiveFilter::Compress),
+            _ => Err(LibArchiveError::UnsupportedFormat(s.to_owned())),
+        }
+    }
+}
+
+/// Creates an archive entry by entry.
+///
+/// For every entry `write_header` is called first, followed by its data through `write_data`
+/// and `finish_entry`; `write_entry` does all three at once.
+pub struct ArchiveWriter {
+    write_archive: WriteArchive,
+    entry: Entry,
+    /// Archive pathname of the first file seen for each `(dev, ino)`, for `add_path`.
+    inodes: HashMap<(u64, u64), String>,
+    /// The archive appended in place; `write_archive` writes to its descriptor.
+    file: Option<std::fs::File>,
+    /// The temporary file that replaces the original archive on `close`.
+    replace: Option<PendingReplace>,
+    /// Entries of the original archive refused while `append` copied them.
+    failed: Vec<FailedEntry>,
+    /// Canonical paths of the files being written, which `add_path` leaves out.
+    own_paths: Vec<PathBuf>,
+}
+
+/// A temporary file next to `target` that takes its place once complete; removed when
+/// dropped before `commit`.
+pub(crate) struct PendingReplace {
+    temp: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+/// Tells apart the temporary files of one process.
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+impl PendingReplace {
+    /// Creates the temporary file, retrying with another name when one is taken.
+    pub(crate) fn new(target: &Path) -> LibArchiveResult<PendingReplace> {
+        Self::with_counter(target, || NEXT_TEMP.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// `new` with the number that tells temporary files apart taken from `next`.
+    fn with_counter(target: &Path, mut next: impl FnMut() -> usize) -> LibArchiveResult<PendingReplace> {
+        let Some(file_name) = target.file_name() else {
+            return Err(LibArchiveError::IsNotFile);
+        };
+
+        loop {
+            let mut temp_name = std::ffi::OsString::from(".");
+            temp_name.push(file_name);
+            temp_name.push(format!(".{}.{}.tmp", std::process::id(), next()));
+            let temp = target.with_file_name(temp_name);
+
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(&temp) {
+                Ok(_) => {
+                    return Ok(PendingReplace {
+                        temp,
+                        target: target.to_path_buf(),
+                        committed: false,
+                    });
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub(crate) fn temp_path(&self) -> &Path {
+        &self.temp
+    }
+
+    /// Gives the temporary file the owner and permissions of the target and renames it over
+    /// the target.
+    pub(crate) fn commit(mut self) -> LibArchiveResult<()> {
+        let meta = std::fs::metadata(&self.target)?;
+        copy_owner(&meta, &self.temp)?;
+        // After the owner, since changing it may clear the setuid and setgid bits.
+        std::fs::set_permissions(&self.temp, meta.permissions())?;
+        std::fs::rename(&self.temp, &self.target)?;
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for PendingReplace {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// A writer for the format `read_archive` has detected, without filters.
+fn same_format_writer(read_archive: &ReadArchive) -> LibArchiveResult<WriteArchive> {
+    let format_code = read_archive.format_code();
+
+    let write_archive = WriteArchive::new()?;
+    if write_archive.set_format(format_code).is_err() {
+        let name = read_archive.format_name().unwrap_or_else(|| format_code.to_string());
+        return Err(LibArchiveError::UnsupportedFormat(name));
+    }
+
+    Ok(write_archive)
+}
+
+/// Opens a temporary file next to `path` for rewriting the archive read by `read_archive`,
+/// with the same format and filters. At least one header must have been read.
+pub(crate) fn open_rewrite(read_archive: &ReadArchive, path: &Path) -> LibArchiveResult<(WriteArchive, PendingReplace)> {
+    let write_archive = same_format_writer(read_archive)?;
+    for filter_code in read_archive.filter_codes() {
+        write_archive.add_filter(filter_code)?;
+    }
+
+    let replace = PendingReplace::new(path)?;
+    let Ok(temp_path) = CString::new(replace.temp_path().to_string_lossy().as_bytes()) else {
+        return Err(LibArchiveError::NulError);
+    };
+    write_archive.open_filename(&temp_path)?;
+
+    Ok((write_archive, replace))
+}
+
+/// How `ArchiveWriter::append` continues an existing archive.
+enum AppendMode {
+    /// Uncompressed tar on unix: new entries overwrite the end marker at this offset.
+    #[cfg(unix)]
+    InPlace(u64),
+    /// Everything else: the first entry has been read and the archive is copied.
+    Rewrite,
+}
+
+/// Creates `file_path` as an empty archive of the given format and filter.
+pub(crate) fn open_write_archive(file_path: &str, format: ArchiveFormat, filter: ArchiveFilter) -> LibArchiveResult<WriteArchive> {
+    let Ok(file_path) = CString::new(file_path) else {
+        return Err(LibArchiveError::NulError);
+    };
+
+    let (Ok(format_name), Ok(filter_name)) = (CString::new(format.name()), CString::new(filter.name())) else {
+        return Err(LibArchiveError::NulError);
+    };
+
+    let write_archive = WriteArchive::new()?;
+    write_archive.set_format_by_name(&format_name)?;
+    if filter != ArchiveFilter::None {
+        write_archive.add_filter_by_name(&filter_name)?;
+    }
+    write_archive.open_filename(&file_path)?;
+
+    Ok(write_archive)
+}
+
+impl ArchiveWriter {
+    pub fn create(file_path: &str, format: ArchiveFormat, filter: ArchiveFilter) -> LibArchiveResult<ArchiveWriter> {
+        let write_archive = open_write_archive(file_path, format, filter)?;
+
+        Ok(ArchiveWriter {
+            write_archive,
+            entry: Entry::new()?,
+            inodes: HashMap::new(),
+            file: None,
+            replace: None,
+            failed: vec!(),
+            own_paths: std::fs::canonicalize(file_path).into_iter().collect(),
+        })
+    }
+
+    /// Opens an existing archive for adding entries, in the format and filters it already has.
+    ///
+    /// On unix an uncompressed tar is continued in place after its last entry. Any other
+    /// archive is copied entry by entry into a temporary file, which replaces the original on
+    /// `close`; dropping the writer without `close` leaves the original untouched. Entries the
+    /// copy refuses are left out and listed by `failed_entries`.
+    pub fn append(file_path: &str) -> LibArchiveResult<ArchiveWriter> {
+        let path = Path::new(file_path);
+        let Ok(meta) = std::fs::metadata(path) else {
+            return Err(LibArchiveError::FailedGetMetaDataFromFile);
+        };
+        if !meta.is_file() {
+            return Err(LibArchiveError::IsNotFile);
+        }
+        let Ok(file_path_cstr) = CString::new(file_path) else {
+            return Err(LibArchiveError::NulError);
+        };
+
+        let read_archive = ReadArchive::new()?;
+        read_archive.support_all_filter_and_format()?;
+        read_archive.open_filename(&file_path_cstr, (meta.len() as usize).clamp(1, crate::MAX_READ_BLOCK_SIZE))?;
+
+        // The format is only known once the first header has been read.
+        let mut entry: *mut ArchiveEntryStruct = std::ptr::null_mut();
+        let has_entries = read_archive.next_header(&mut entry)?;
+
+        // Writing in place needs a descriptor libarchive can take, which is unix only.
+        #[cfg(unix)]
+        let mode = {
+            let is_tar = read_archive.format_code() & libarchive3_sys::ARCHIVE_FORMAT_BASE_MASK == libarchive3_sys::ARCHIVE_FORMAT_TAR;
+            if is_tar && read_archive.filter_codes().is_empty() {
+                if has_entries {
+                    while read_archive.next_header(&mut entry)? {}
+                }
+                AppendMode::InPlace(read_archive.header_position().max(0) as u64)
+            } else {
+                AppendMode::Rewrite
+            }
+        };
+        #[cfg(not(unix))]
+        let mode = AppendMode::Rewrite;
+
+        let writer = match mode {
+            #[cfg(unix)]
+            AppendMode::InPlace(position) => {
+                let write_archive = same_format_writer(&read_archive)?;
+                drop(read_archive);
+                let file = open_at(path, position)?;
+                write_archive.open_fd(raw_fd(&file))?;
+
+                ArchiveWriter {
+                    write_archive,
+                    entry: Entry::new()?,
+                    inodes: HashMap::new(),
+                    file: Some(file),
+                    replace: None,
+                    failed: vec!(),
+                    own_paths: std::fs::canonicalize(path).into_iter().collect(),
+                }
+            },
+            AppendMode::Rewrite => {
+                let (write_archive, replace) = open_rewrite(&read_archive, path)?;
+
+                let mut failed: Vec<FailedEntry> = vec!();
+                if has_entries {
+                    loop {
+                        if let Some(error) = copy_entry(&read_archive, &write_archive, entry)? {
+                            failed.push(FailedEntry {
+                                pathname: lossy_pathname_from_entry(entry),
+                                error,
+                            });
+                        }
+                        if !read_archive.next_header(&mut entry)? {
+                            break;
+                        }
+                    }
+                }
+                read_archive.close()?;
+                let own_paths = [path, replace.temp_path()].iter()
+                    .filter_map(|v| std::fs::canonicalize(v).ok())
+                    .collect();
+
+                ArchiveWriter {
+                    write_archive,
+                    entry: Entry::new()?,
+                    inodes: HashMap::new(),
+                    file: None,
+                    replace: Some(replace),
+                    failed,
+                    own_paths,
+                }
+            },
+        };
+
+        Ok(writer)
+    }
+
+    /// Entries of the original archive that `append` could not copy; they are missing from
+    /// the result. Empty for every other writer.
+    pub fn failed_entries(&self) -> &[FailedEntry] {
+        &self.failed
+    }
+
+    /// Writes the header of the next entry; file entries need `size` to be set.
+    pub fn write_header(&mut self, metadata: &EntryMetadata) -> LibArchiveResult<()> {
+        self.entry.clear();
+        metadata.fill_entry(self.entry.as_ptr())?;
+
+        self.write_archive.write_header(&self.entry)
+    }
+
+    pub fn write_data(&mut self, data: &[u8]) -> LibArchiveResult<()> {
+        self.write_archive.write_data(data)
+    }
+
+    pub fn finish_entry(&mut self) -> LibArchiveResult<()> {
+        self.write_archive.finish_entry()
+    }
+
+    /// Writes a whole entry; the size of a file entry is taken from `data`.
+    pub fn write_entry(&mut self, metadata: &EntryMetadata, data: &[u8]) -> LibArchiveResult<()> {
+        if metadata.entry_type == EntryType::File && metadata.size != Some(data.len() as u64) {
+            let mut metadata = metadata.clone();
+            metadata.size = Some(data.len() as u64);
+            self.write_header(&metadata)?;
+        } else {
+            self.write_header(metadata)?;
+        }
+
+        if !data.is_empty() {
+            self.write_data(data)?;
+        }
+        self.finish_entry()
+    }
+
+    /// Adds `path` from disk as `name`, descending into directories.
+    ///
+    /// Symlinks are stored as links, and files that share an inode with a file added earlier
+    /// are stored as hardlinks to it. The archive being written is skipped, so that it can sit
+    /// inside one of the directories added.
+    pub fn add_path(&mut self, path: &Path, name: &str) -> LibArchiveResult<()> {
+        let meta = std::fs::symlink_metadata(path)?;
+        let mut metadata = metadata_from_disk(name, &meta);
+
+        match metadata.entry_type {
+            EntryType::Directory => {
+                self.write_entry(&metadata, b"")?;
+
+                let mut children: Vec<_> = std::fs::read_dir(path)?.collect::<Result<_, _>>()?;
+                children.sort_by_key(|v| v.file_name());
+                for child in children {
+                    let child_name = format!("{}/{}", name.trim_end_matches('/'), child.file_name().to_string_lossy());
+                    self.add_path(&child.path(), &child_name)?;
+                }
+
+                Ok(())
+            },
+            EntryType::Symlink => {
+                metadata.symlink = Some(std::fs::read_link(path)?.to_string_lossy().into_owned());
+                self.write_entry(&metadata, b"")
+            },
+            EntryType::File => {
+                if !self.own_paths.is_empty() && std::fs::canonicalize(path).is_ok_and(|v| self.own_paths.contains(&v)) {
+                    return Ok(());
+                }
+
+                if let Some(key) = inode_key(&meta) {
+                    if let Some(target) = self.inodes.get(&key) {
+                        metadata.entry_type = EntryType::Hardlink;
+                        metadata.hardlink = Some(target.clone());
+                        metadata.size = Some(0);
+                        return self.write_entry(&metadata, b"");
+                    }
+                    self.inodes.insert(key, name.to_owned());
+                }
+
+                metadata.size = Some(meta.len());
+                self.write_header(&metadata)?;
+
+                let mut file = std::fs::File::open(path)?;
+                let mut buf = vec![0_u8; 64 * 1024];
+                loop {
+                    let n = file.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    self.write_data(&buf[..n])?;
+                }
+                self.finish_entry()
+            },
+            _ => self.write_entry(&metadata, b""),
+        }
+    }
+
+    pub fn close(self) -> LibArchiveResult<()> {
+        let ArchiveWriter { write_archive, file, replace, .. } = self;
+
+        write_archive.close()?;
+        drop(file);
+        if let Some(replace) = replace {
+            replace.commit()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The metadata of a file on disk stored as `name`, without link targets.
+pub(crate) fn metadata_from_disk(name: &str, meta: &std::fs::Metadata) -> EntryMetadata {
+    let file_type = meta.file_type();
+    let entry_type = if file_type.is_dir() {
+        EntryType::Directory
+    } else if file_type.is_symlink() {
+        EntryType::Symlink
+    } else if file_type.is_file() {
+        EntryType::File
+    } else {
+        special_entry_type(&file_type)
+    };
+
+    let mut metadata = EntryMetadata::new(name, entry_type);
+    fill_from_disk(&mut metadata, meta);
+
+    metadata
+}
+
+/// Opens `path` for writing at `position`.
+#[cfg(unix)]
+fn open_at(path: &Path, position: u64) -> LibArchiveResult<std::fs::File> {
+    use std::io::{Seek, SeekFrom};
+
+    let mut file = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+    file.seek(SeekFrom::Start(position))?;
+
+    Ok(file)
+}
+
+#[cfg(unix)]
+fn raw_fd(file: &std::fs::File) -> libc::c_int {
+    use std::os::unix::io::AsRawFd;
+
+    file.as_raw_fd()
+}
+
+/// Gives `path` the owner and group of `meta` where they differ. Only root can change the
+/// owner; the group can be changed to any group the user is in.
+#[cfg(unix)]
+fn copy_owner(meta: &std::fs::Metadata, path: &Path) -> LibArchiveResult<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let current = std::fs::metadata(path)?;
+    let uid = Some(meta.uid()).filter(|v| *v != current.uid());
+    let gid = Some(meta.gid()).filter(|v| *v != current.gid());
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(path, uid, gid)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_meta: &std::fs::Metadata, _path: &Path) -> LibArchiveResult<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn special_entry_type(file_type: &std::fs::FileType) -> EntryType {
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_fifo() {
+        EntryType::Fifo
+    } else if file_type.is_socket() {
+        EntryType::Socket
+    } else if file_type.is_char_device() {
+        EntryType::CharacterDevice
+    } else if file_type.is_block_device() {
+        EntryType::BlockDevice
+    } else {
+        EntryType::Unknown
+    }
+}
+
+#[cfg(not(unix))]
+fn special_entry_type(_file_type: &std::fs::FileType) -> EntryType {
+    EntryType::Unknown
+}
+
+#[cfg(unix)]
+fn fill_from_disk(metadata: &mut EntryMetadata, meta: &std::fs::Metadata) {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.mode = meta.mode() & 0o7777;
+    metadata.uid = meta.uid() as i64;
+    metadata.gid = meta.gid() as i64;
+    metadata.uname = owner::system_user_name(meta.uid());
+    metadata.gname = owner::system_group_name(meta.gid());
+    metadata.mtime = Some(meta.mtime());
+    metadata.mtime_nsec = meta.mtime_nsec();
+}
+
+#[cfg(not(unix))]
+fn fill_from_disk(metadata: &mut EntryMetadata, meta: &std::fs::Metadata) {
+    if meta.permissions().readonly() {
+        metadata.mode &= !0o222;
+    }
+    metadata.mtime = meta.modified().ok()
+        .and_then(|v| v.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|v| v.as_secs() as i64);
+}
+
+#[cfg(unix)]
+fn inode_key(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    if meta.nlink() < 2 {
+        return None;
+    }
+
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode_key(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArchiveExt;
+    use crate::tests::TempDir;
+
+    #[test]
+    fn add_path_skips_the_archive_being_written() {
+        let dir = TempDir::new("writer-own-path");
+        std::fs::write(dir.0.join("a.txt"), b"a").unwrap();
+        let path = dir.0.join("out.tar");
+        let path_str = path.to_str().unwrap();
+
+        let mut writer = ArchiveWriter::create(path_str, ArchiveFormat::Pax, ArchiveFilter::None).unwrap();
+        writer.add_path(&dir.0, "d").unwrap();
+        writer.close().unwrap();
+
+        let report = crate::Archive.list(path_str).unwrap();
+        let names: Vec<_> = report.entries.iter().map(|v| v.pathname.as_str()).collect();
+        assert_eq!(names, ["d/", "d/a.txt"]);
+    }
+
+    #[test]
+    fn append_skips_the_archive_and_its_copy() {
+        let dir = TempDir::new("writer-append-own-path");
+        for format in [ArchiveFormat::Pax, ArchiveFormat::Zip] {
+            let path = dir.0.join(format!("out.{}", format.name()));
+            let path_str = path.to_str().unwrap();
+            ArchiveWriter::create(path_str, format, ArchiveFilter::None).unwrap().close().unwrap();
+
+            let mut writer = ArchiveWriter::append(path_str).unwrap();
+            writer.add_path(&dir.0, "d").unwrap();
+            writer.close().unwrap();
+
+            let report = crate::Archive.list(path_str).unwrap();
+            assert!(report.entries.iter().all(|v| !v.pathname.ends_with(&format!(".{}", format.name()))), "{:?}", report.entries);
+            assert!(report.entries.iter().all(|v| !v.pathname.ends_with(".tmp")));
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[test]
+    fn pending_replace_names_are_unique() {
+        let dir = TempDir::new("pending-unique");
+        let target = dir.0.join("a.tar");
+
+        let first = PendingReplace::new(&target).unwrap();
+        let second = PendingReplace::new(&target).unwrap();
+        assert_ne!(first.temp_path(), second.temp_path());
+        assert!(first.temp_path().exists());
+        assert!(second.temp_path().exists());
+
+        let temp = first.temp_path().to_path_buf();
+        drop(first);
+        assert!(!temp.exists());
+    }
+
+    #[test]
+    fn pending_replace_skips_taken_names() {
+        let dir = TempDir::new("pending-taken");
+        let target = dir.0.join("a.tar");
+
+        let taken = dir.0.join(format!(".a.tar.{}.0.tmp", std::process::id()));
+        std::fs::write(&taken, b"keep").unwrap();
+
+        let mut counter = 0..;
+        let replace = PendingReplace::with_counter(&target, || counter.next().unwrap()).unwrap();
+        assert_eq!(replace.temp_path(), dir.0.join(format!(".a.tar.{}.1.tmp", std::process::id())));
+        drop(replace);
+        assert_eq!(std::fs::read(&taken).unwrap(), b"keep");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn commit_keeps_mode_and_group() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = TempDir::new("pending-commit");
+        let target = dir.0.join("a.tar");
+        std::fs::write(&target, b"old").unwrap();
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let before = std::fs::metadata(&target).unwrap();
+
+        let replace = PendingReplace::new(&target).unwrap();
+        std::fs::write(replace.temp_path(), b"new").unwrap();
+        replace.commit().unwrap();
+
+        let after = std::fs::metadata(&target).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"new");
+        assert_eq!(after.mode() & 0o7777, 0o640);
+        assert_eq!((after.uid(), after.gid()), (before.uid(), before.gid()));
+    }
+}